@@ -0,0 +1,149 @@
+use crate::{
+    env::Env,
+    exec::Hook,
+    instr::{Instr, Program},
+};
+
+fn ratio(jumped: usize, ran: usize) -> String {
+    match ran {
+        0 => String::from("never ran"),
+        ran => format!(
+            "jumps {jumped}/{ran} ({:.1}%)",
+            jumped as f64 * 100.0 / ran as f64
+        ),
+    }
+}
+
+/// Counts how often every `IF` jumps, and how often `GOTO`s run.
+pub struct Branches {
+    /// Times each instruction jumped and ran, by function and pc. Only `IF`s
+    /// are counted.
+    conds: Vec<Vec<[usize; 2]>>,
+    gotos: usize,
+    /// Function, pc and direction of the `IF` being executed.
+    pending: Option<(usize, usize, bool)>,
+}
+
+impl Branches {
+    pub fn new(program: &Program) -> Self {
+        Branches {
+            conds: program
+                .funcs
+                .iter()
+                .map(|func| vec![[0, 0]; func.body.len()])
+                .collect(),
+            gotos: 0,
+            pending: None,
+        }
+    }
+
+    /// Totals of `IF`s and `GOTO`s, followed by the listing of the program
+    /// with every `IF` annotated.
+    pub fn report(&self, program: &Program) -> String {
+        let [jumped, ran] = self
+            .conds
+            .iter()
+            .flatten()
+            .fold([0, 0], |[jumped, ran], counts| {
+                [jumped + counts[0], ran + counts[1]]
+            });
+        let mut lines = vec![
+            format!("IF: {}", ratio(jumped, ran)),
+            format!("GOTO: {}", self.gotos),
+            format!("jumps: {}", jumped + self.gotos),
+        ];
+        for func in program.funcs.iter().filter(|func| func.builtin.is_none()) {
+            let texts: Vec<String> = func
+                .body
+                .iter()
+                .map(|instr| match instr {
+                    Instr::Label(_) => instr.to_string(),
+                    _ => format!("  {instr}"),
+                })
+                .collect();
+            let width = texts.iter().map(String::len).max().unwrap_or_default();
+            lines.push(String::new());
+            let private = if func.private { "PRIVATE " } else { "" };
+            lines.push(format!("{private}FUNCTION {} :", func.name));
+            for (pc, text) in texts.into_iter().enumerate() {
+                lines.push(match func.body[pc] {
+                    Instr::Cond { .. } => {
+                        let [jumped, ran] = self.conds[func.id][pc];
+                        format!("{text:<width$}  {}", ratio(jumped, ran))
+                    }
+                    _ => text,
+                });
+            }
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+impl Hook for Branches {
+    fn before(&mut self, program: &Program, env: &Env) {
+        let frame = env.top_frame();
+        match program.funcs[frame.func].body.get(frame.pc) {
+            Some(Instr::Goto { .. }) => self.gotos += 1,
+            Some(instr) => {
+                if let Some(jumps) = instr.jumps(env) {
+                    self.pending = Some((frame.func, frame.pc, jumps))
+                }
+            }
+            None => (),
+        }
+    }
+
+    fn after(&mut self, _program: &Program, _env: &Env) {
+        if let Some((func, pc, jumps)) = self.pending.take() {
+            let counts = &mut self.conds[func][pc];
+            counts[0] += jumps as usize;
+            counts[1] += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::tests::run_with;
+
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let (program, branches, _) = run_with(
+            "FUNCTION main :
+             i := #0
+             LABEL loop :
+             IF i >= #10 GOTO end
+             IF i == #3 GOTO skip
+             WRITE i
+             LABEL skip :
+             i := i + #1
+             GOTO loop
+             LABEL end :
+             IF i < #0 GOTO end
+             RETURN #0",
+            Branches::new,
+        );
+        assert_eq!(
+            branches.report(&program),
+            "IF: jumps 2/22 (9.1%)
+GOTO: 10
+jumps: 12
+
+FUNCTION main :
+  i := #0
+LABEL loop :
+  IF i >= #10 GOTO end  jumps 1/11 (9.1%)
+  IF i == #3 GOTO skip  jumps 1/10 (10.0%)
+  WRITE i
+LABEL skip :
+  i := i + #1
+  GOTO loop
+LABEL end :
+  IF i < #0 GOTO end    jumps 0/1 (0.0%)
+  RETURN #0
+"
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use std::fmt::Display;
+
+use crate::{
+    env::{Effect, Env},
+    expr::Expr,
+    instr::{Instr, Program},
+    value::Value,
+};
+
+/// Where a breakpoint stops, as written after `break`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// `fib`, the entry of a function.
+    Func(String),
+    /// `main:loop`, a `LABEL` inside a function.
+    Label(String, String),
+    /// `12` or `a.ir:12`, the first instruction on a source line.
+    Line(Option<String>, usize),
+}
+
+impl Location {
+    pub fn parse(loc: &str) -> Location {
+        match loc.rsplit_once(':') {
+            Some((file, line)) => match line.parse() {
+                Ok(line) => Location::Line(Some(String::from(file)), line),
+                Err(_) => Location::Label(String::from(file), String::from(line)),
+            },
+            None => match loc.parse() {
+                Ok(line) => Location::Line(None, line),
+                Err(_) => Location::Func(String::from(loc)),
+            },
+        }
+    }
+
+    /// Finds the `(func, pc)` positions this location refers to.
+    pub fn resolve(&self, program: &Program) -> Result<Vec<(usize, usize)>, String> {
+        let user_funcs = program.funcs.iter().filter(|func| func.builtin.is_none());
+        let sites: Vec<(usize, usize)> = match self {
+            Location::Func(name) => user_funcs
+                .filter(|func| func.name == *name)
+                .map(|func| (func.id, 0))
+                .collect(),
+            Location::Label(name, label) => user_funcs
+                .filter(|func| func.name == *name)
+                .flat_map(|func| {
+                    func.body
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(pc, instr)| match instr {
+                            Instr::Label(name) if name == label => Some((func.id, pc)),
+                            _ => None,
+                        })
+                })
+                .collect(),
+            Location::Line(file, line) => user_funcs
+                .filter(|func| file.as_ref().is_none_or(|file| program.file(func) == file))
+                .filter_map(|func| {
+                    let pc = func.lines.iter().position(|l| l == line)?;
+                    Some((func.id, pc))
+                })
+                .collect(),
+        };
+        match sites.is_empty() {
+            true => Err(format!("no instruction at {self}")),
+            false => Ok(sites),
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Func(name) => write!(f, "{name}"),
+            Location::Label(name, label) => write!(f, "{name}:{label}"),
+            Location::Line(Some(file), line) => write!(f, "{file}:{line}"),
+            Location::Line(None, line) => write!(f, "{line}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub loc: Location,
+    sites: Vec<(usize, usize)>,
+    /// Condition that must hold for the breakpoint to be hit, as written.
+    pub cond: Option<String>,
+    expr: Option<Expr>,
+    /// Number of times execution reached this breakpoint with its condition
+    /// holding.
+    pub hits: usize,
+    /// Number of upcoming hits that do not stop.
+    pub ignore: usize,
+    /// Debugger commands run whenever the program stops here.
+    pub commands: Vec<String>,
+}
+
+impl Breakpoint {
+    /// Sets the condition, or removes it if `cond` is `None`.
+    pub fn set_cond(&mut self, cond: Option<&str>) -> Result<(), String> {
+        self.expr = cond.map(Expr::parse).transpose()?;
+        self.cond = cond.map(String::from);
+        Ok(())
+    }
+
+    /// Whether the breakpoint is at `(func, pc)` and its condition holds
+    /// according to `holds`.
+    fn stops(&self, func: usize, pc: usize, holds: &mut impl FnMut(usize, &Expr) -> bool) -> bool {
+        self.sites.contains(&(func, pc))
+            && self.expr.as_ref().is_none_or(|expr| holds(self.id, expr))
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.id, self.loc)?;
+        if let Some(cond) = &self.cond {
+            write!(f, " if {cond}")?;
+        }
+        write!(f, ", hit {} times", self.hits)?;
+        if self.ignore > 0 {
+            write!(f, ", ignoring next {} hits", self.ignore)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a watchpoint watches.
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Variable `id` in every frame of function `func`.
+    Var { func: usize, id: usize },
+    /// The cell this pointer points to, whichever pointer it is written through.
+    Cell(Value),
+}
+
+impl Target {
+    /// Parses `fib:n` or `n` as a variable, and `*p` as the cell `p` points to
+    /// in the current frame.
+    pub fn parse(target: &str, program: &Program, env: &Env) -> Result<Target, String> {
+        let frame = env.top_frame();
+        let var = |func: usize, name: &str| {
+            program.funcs[func]
+                .var(name)
+                .map(|id| (func, id))
+                .ok_or_else(|| format!("no variable {name} in {}", program.funcs[func].name))
+        };
+        if let Some(name) = target.strip_prefix('*') {
+            let (_, id) = var(frame.func, name)?;
+            return match frame.get(&id) {
+                Some(ptr @ Value::ValPtr { .. }) => Ok(Target::Cell(ptr.clone())),
+                _ => Err(format!("{name} is not a pointer")),
+            };
+        }
+        let (func, id) = match target.split_once(':') {
+            Some((func, name)) => {
+                let func = program
+                    .funcs
+                    .iter()
+                    .find(|f| f.name == func && f.builtin.is_none())
+                    .ok_or_else(|| format!("no function {func}"))?;
+                var(func.id, name)?
+            }
+            None => var(frame.func, target)?,
+        };
+        Ok(Target::Var { func, id })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub name: String,
+    pub target: Target,
+    /// Whether a change stops execution, rather than only being logged.
+    pub stop: bool,
+    pub hits: usize,
+}
+
+impl Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} on {}, hit {} times", self.id, self.name, self.hits)
+    }
+}
+
+/// A change seen by a watchpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub id: usize,
+    pub old: Value,
+    pub new: Value,
+    pub stop: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    watches: Vec<Watchpoint>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            list: Vec::new(),
+            watches: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Adds a breakpoint at `loc`, returning its id.
+    pub fn add(&mut self, program: &Program, loc: Location) -> Result<usize, String> {
+        let sites = loc.resolve(program)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.list.push(Breakpoint {
+            id,
+            loc,
+            sites,
+            cond: None,
+            expr: None,
+            hits: 0,
+            ignore: 0,
+            commands: Vec::new(),
+        });
+        Ok(id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Result<&mut Breakpoint, String> {
+        self.list
+            .iter_mut()
+            .find(|bp| bp.id == id)
+            .ok_or_else(|| format!("no breakpoint {id}"))
+    }
+
+    /// Adds a watchpoint on `target`, returning its id.
+    pub fn watch(&mut self, name: &str, target: Target, stop: bool) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watches.push(Watchpoint {
+            id,
+            name: String::from(name),
+            target,
+            stop,
+            hits: 0,
+        });
+        id
+    }
+
+    /// Deletes a breakpoint or watchpoint.
+    pub fn delete(&mut self, id: usize) -> Result<(), String> {
+        if !self.list.iter().any(|bp| bp.id == id) && !self.watches.iter().any(|wp| wp.id == id) {
+            return Err(format!("no breakpoint {id}"));
+        }
+        self.list.retain(|bp| bp.id != id);
+        self.watches.retain(|wp| wp.id != id);
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.list.iter()
+    }
+
+    pub fn watches(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watches.iter()
+    }
+
+    /// Finds what `effect` changed according to `wp`, as old and new value.
+    /// Writing the value a target already holds changes nothing.
+    fn change(wp: &Watchpoint, env: &Env, effect: &Effect) -> Option<(Value, Value)> {
+        match (&wp.target, effect) {
+            (
+                Target::Var { func, id },
+                Effect::Set {
+                    depth,
+                    id: var,
+                    old,
+                    new,
+                },
+            ) if env
+                .stack()
+                .get(*depth)
+                .is_some_and(|frame| frame.func == *func)
+                && var == id
+                && old != new =>
+            {
+                Some((old.clone(), new.clone()))
+            }
+            (Target::Cell(cell), Effect::Store { ptr, old, new })
+                if cell.same_cell(ptr) && old != new =>
+            {
+                Some((Value::new_int(*old), Value::new_int(*new)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Counts a hit of every watchpoint on what `effects` changed in `env`.
+    pub fn changes(&mut self, env: &Env, effects: &[Effect]) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for effect in effects {
+            for wp in self.watches.iter_mut() {
+                if let Some((old, new)) = Self::change(wp, env, effect) {
+                    wp.hits += 1;
+                    changes.push(Change {
+                        id: wp.id,
+                        old,
+                        new,
+                        stop: wp.stop,
+                    })
+                }
+            }
+        }
+        changes
+    }
+
+    /// Finds a stopping watchpoint on what `effects` changed in `env`,
+    /// without counting a hit.
+    pub fn watched(&self, env: &Env, effects: &[Effect]) -> Option<&Watchpoint> {
+        self.watches.iter().find(|wp| {
+            wp.stop
+                && effects
+                    .iter()
+                    .any(|effect| Self::change(wp, env, effect).is_some())
+        })
+    }
+
+    /// Finds a breakpoint at `(func, pc)` whose condition holds according to
+    /// `holds`, without counting a hit.
+    pub fn at(
+        &self,
+        func: usize,
+        pc: usize,
+        mut holds: impl FnMut(usize, &Expr) -> bool,
+    ) -> Option<usize> {
+        self.list
+            .iter()
+            .find(|bp| bp.stops(func, pc, &mut holds))
+            .map(|bp| bp.id)
+    }
+
+    /// Counts a hit of every breakpoint at `(func, pc)` whose condition holds
+    /// according to `holds`, returning the id of the first one that stops.
+    pub fn hit(
+        &mut self,
+        func: usize,
+        pc: usize,
+        mut holds: impl FnMut(usize, &Expr) -> bool,
+    ) -> Option<usize> {
+        let mut stop = None;
+        for bp in self.list.iter_mut() {
+            if !bp.stops(func, pc, &mut holds) {
+                continue;
+            }
+            bp.hits += 1;
+            if bp.ignore > 0 {
+                bp.ignore -= 1
+            } else if stop.is_none() {
+                stop = Some(bp.id)
+            }
+        }
+        stop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let mut program = Parser::with_file(
+            "a.ir",
+            "FUNCTION main :
+             i := #0
+             LABEL loop :
+             i := i + #1
+             IF i < #3 GOTO loop
+             RETURN #0",
+        )
+        .parse();
+        program.init();
+
+        let resolve = |loc: &str| Location::parse(loc).resolve(&program);
+        assert_eq!(resolve("main"), Ok(vec![(0, 0)]));
+        assert_eq!(resolve("main:loop"), Ok(vec![(0, 1)]));
+        assert_eq!(resolve("4"), Ok(vec![(0, 2)]));
+        assert_eq!(resolve("a.ir:5"), Ok(vec![(0, 3)]));
+        assert_eq!(
+            resolve("b.ir:5"),
+            Err(String::from("no instruction at b.ir:5"))
+        );
+        assert_eq!(resolve("fib"), Err(String::from("no instruction at fib")));
+
+        let mut bps = Breakpoints::new();
+        let id = bps.add(&program, Location::parse("main:loop")).unwrap();
+        bps.get_mut(id).unwrap().ignore = 1;
+        assert_eq!(bps.hit(0, 0, |_, _| true), None);
+        assert_eq!(bps.hit(0, 1, |_, _| true), None);
+        assert_eq!(bps.hit(0, 1, |_, _| true), Some(id));
+        assert_eq!(bps.get_mut(id).unwrap().hits, 2);
+
+        let bp = bps.get_mut(id).unwrap();
+        assert!(bp.set_cond(Some("i ==")).is_err());
+        bp.set_cond(Some("i == 3")).unwrap();
+        assert_eq!(bp.to_string(), "1 at main:loop if i == 3, hit 2 times");
+        assert_eq!(bps.hit(0, 1, |_, _| false), None);
+        assert_eq!(bps.at(0, 1, |_, _| true), Some(id));
+        assert_eq!(bps.get_mut(id).unwrap().hits, 2);
+    }
+}
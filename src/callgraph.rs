@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    env::Env,
+    exec::Hook,
+    instr::{Instr, Program},
+};
+
+/// Escapes `text` for a quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Records every call made, by caller and callee.
+pub struct CallGraph {
+    /// Number of calls along each edge, by function ids.
+    edges: BTreeMap<(usize, usize), usize>,
+    /// Caller and callee of the `CALL` being executed.
+    pending: Option<(usize, usize)>,
+}
+
+impl CallGraph {
+    pub fn new() -> Self {
+        CallGraph {
+            edges: BTreeMap::new(),
+            pending: None,
+        }
+    }
+
+    /// The graph in Graphviz DOT. Functions that were never called are
+    /// dashed, and intrinsics are boxes.
+    pub fn report(&self, program: &Program) -> String {
+        let mut calls = vec![0; program.funcs.len()];
+        calls[program.entry] = 1;
+        for (&(_, callee), count) in &self.edges {
+            calls[callee] += count
+        }
+        let mut lines = vec![String::from("digraph callgraph {")];
+        for func in &program.funcs {
+            let name = match func.private {
+                true => escape(&format!("{} ({})", func.name, program.file(func))),
+                false => escape(&func.name),
+            };
+            let label = match calls[func.id] {
+                1 => format!("{name}\\n1 call"),
+                calls => format!("{name}\\n{calls} calls"),
+            };
+            let attrs = match (func.builtin, calls[func.id]) {
+                (Some(_), 0) => continue,
+                (Some(_), _) => format!("label=\"{label}\", shape=box"),
+                (None, 0) => format!("label=\"{name}\", style=dashed, color=gray"),
+                (None, _) => format!("label=\"{label}\""),
+            };
+            lines.push(format!("    f{} [{attrs}];", func.id));
+        }
+        for (&(caller, callee), count) in &self.edges {
+            lines.push(format!("    f{caller} -> f{callee} [label=\"{count}\"];"));
+        }
+        lines.push(String::from("}"));
+        lines.join("\n") + "\n"
+    }
+}
+
+impl Hook for CallGraph {
+    fn before(&mut self, program: &Program, env: &Env) {
+        let frame = env.top_frame();
+        if let Some(Instr::Call { id, .. }) = program.funcs[frame.func].body.get(frame.pc) {
+            self.pending = Some((frame.func, *id))
+        }
+    }
+
+    fn after(&mut self, _program: &Program, _env: &Env) {
+        if let Some(edge) = self.pending.take() {
+            *self.edges.entry(edge).or_default() += 1
+        }
+    }
+
+    fn stop(&mut self, program: &Program, env: &Env) {
+        // `exit` stops the program, but was called all the same.
+        self.after(program, env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{exec::tests::run_with, parser::Parser};
+
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let (program, graph, _) = run_with(
+            "FUNCTION fact :
+             PARAM n
+             IF n > #1 GOTO rec
+             RETURN #1
+             LABEL rec :
+             m := n - #1
+             r := CALL fact(m)
+             r := r * n
+             RETURN r
+
+             FUNCTION unused :
+             RETURN #0
+
+             FUNCTION main :
+             x := CALL fact(#3)
+             y := CALL fact(#2)
+             CALL exit(#0)",
+            |_| CallGraph::new(),
+        );
+        assert_eq!(
+            graph.report(&program),
+            "digraph callgraph {
+    f0 [label=\"fact\\n5 calls\"];
+    f1 [label=\"unused\", style=dashed, color=gray];
+    f2 [label=\"main\\n1 call\"];
+    f3 [label=\"exit\\n1 call\", shape=box];
+    f0 -> f0 [label=\"3\"];
+    f2 -> f0 [label=\"2\"];
+    f2 -> f3 [label=\"1\"];
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        let mut program = Parser::with_file(
+            "lab\\\"1\".ir",
+            "PRIVATE FUNCTION f :\nRETURN #0\nFUNCTION main :\nRETURN #0",
+        )
+        .parse();
+        program.init();
+        assert_eq!(
+            CallGraph::new().report(&program),
+            "digraph callgraph {
+    f0 [label=\"f (lab\\\\\\\"1\\\".ir)\", style=dashed, color=gray];
+    f1 [label=\"main\\n1 call\"];
+}
+"
+        );
+    }
+}
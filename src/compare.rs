@@ -0,0 +1,229 @@
+use crate::{
+    debug::catch,
+    exec::{ImplicitReturn, Interpreter},
+    instr::Program,
+    profile::Profile,
+};
+
+/// What running a program produced.
+pub struct Outcome {
+    /// Output written before the program stopped.
+    pub output: String,
+    /// Number of instructions executed, or the error the program failed with.
+    pub result: Result<usize, String>,
+    /// Instructions executed in each function that was called, by name, and
+    /// by name and file for `PRIVATE` functions.
+    pub funcs: Vec<(String, usize)>,
+}
+
+impl Outcome {
+    pub fn run(program: Program, input: &[u8], implicit_return: ImplicitReturn) -> Outcome {
+        let mut interpreter = Interpreter::new(program, input, Vec::new());
+        interpreter.set_implicit_return(implicit_return);
+        let mut profile = Profile::new(interpreter.program());
+        let result = catch(|| interpreter.exec_with(&mut [&mut profile]));
+        let program = interpreter.program();
+        let funcs = profile
+            .exclusive(program)
+            .into_iter()
+            .map(|(func, count)| match func.private {
+                true => (format!("{} ({})", func.name, program.file(func)), count),
+                false => (func.name.clone(), count),
+            })
+            .collect();
+        let output = interpreter.into_output();
+        Outcome {
+            output: String::from_utf8_lossy(&output).into_owned(),
+            result,
+            funcs,
+        }
+    }
+
+    fn count(&self, key: &str) -> Option<usize> {
+        self.funcs
+            .iter()
+            .find(|(func, _)| func == key)
+            .map(|(_, count)| *count)
+    }
+}
+
+fn row(a: Option<usize>, b: Option<usize>, name: &str) -> String {
+    let show = |count: Option<usize>| count.map_or(String::from("-"), |count| count.to_string());
+    let a_cnt = a.unwrap_or_default() as i64;
+    let b_cnt = b.unwrap_or_default() as i64;
+    let percent = match a_cnt {
+        0 => String::from("-"),
+        a_cnt => format!("{:+.1}%", (b_cnt - a_cnt) as f64 * 100.0 / a_cnt as f64),
+    };
+    format!(
+        "{:>10} {:>10} {:>+10} {percent:>8}  {name}",
+        show(a),
+        show(b),
+        b_cnt - a_cnt
+    )
+}
+
+/// Compares the outcomes of running programs `a` and `b`, named `names`,
+/// on the same input. Returns the report and whether both ran to the end and
+/// their outputs agree.
+pub fn compare(names: [&str; 2], a: &Outcome, b: &Outcome) -> (String, bool) {
+    let mut lines = vec![format!("a: {}", names[0]), format!("b: {}", names[1])];
+    for (id, outcome) in [("a", a), ("b", b)] {
+        if let Err(err) = &outcome.result {
+            lines.push(format!("{id} failed: {err}"))
+        }
+    }
+    let (a_lines, b_lines): (Vec<&str>, Vec<&str>) =
+        (a.output.lines().collect(), b.output.lines().collect());
+    let same = a.output == b.output;
+    match (0..a_lines.len().max(b_lines.len())).find(|&i| a_lines.get(i) != b_lines.get(i)) {
+        _ if same => lines.push(format!("output: identical, {} lines", a_lines.len())),
+        Some(i) => {
+            let show =
+                |line: Option<&&str>| line.map_or(String::from("nothing"), |line| line.to_string());
+            lines.push(format!(
+                "output: differs at line {}, a wrote {} and b wrote {}",
+                i + 1,
+                show(a_lines.get(i)),
+                show(b_lines.get(i))
+            ))
+        }
+        None => lines.push(String::from("output: differs in line endings")),
+    }
+    lines.push(format!(
+        "{:>10} {:>10} {:>10} {:>8}  function",
+        "a", "b", "diff", "%"
+    ));
+    lines.push(row(a.result.clone().ok(), b.result.clone().ok(), "total"));
+    let b_only = b.funcs.iter().filter(|(key, _)| a.count(key).is_none());
+    for (key, _) in a.funcs.iter().chain(b_only) {
+        lines.push(row(a.count(key), b.count(key), key));
+    }
+    let ok = a.result.is_ok() && b.result.is_ok();
+    (lines.join("\n") + "\n", same && ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+
+    use super::*;
+
+    const SLOW: &str = "FUNCTION square :
+PARAM n
+r := n * n
+RETURN r
+
+FUNCTION main :
+READ n
+i := #0
+LABEL loop :
+IF i >= n GOTO end
+s := CALL square(i)
+WRITE s
+i := i + #1
+GOTO loop
+LABEL end :
+RETURN #0
+";
+
+    const FAST: &str = "FUNCTION main :
+READ n
+i := #0
+LABEL loop :
+IF i >= n GOTO end
+s := i * i
+WRITE s
+i := i + #1
+GOTO loop
+LABEL end :
+RETURN #0
+";
+
+    fn outcome(code: &str) -> Outcome {
+        let program = Parser::from(code).parse();
+        Outcome::run(program, "3\n".as_bytes(), ImplicitReturn::Error)
+    }
+
+    #[test]
+    fn test_compare() {
+        let (report, same) = compare(["slow.ir", "fast.ir"], &outcome(SLOW), &outcome(FAST));
+        assert!(same);
+        assert_eq!(
+            report,
+            "a: slow.ir
+b: fast.ir
+output: identical, 3 lines
+         a          b       diff        %  function
+        35         23        -12   -34.3%  total
+         9          -         -9  -100.0%  square
+        26         23         -3   -11.5%  main
+"
+        );
+    }
+
+    #[test]
+    fn test_differ() {
+        let wrong = FAST.replace("s := i * i", "s := i + i");
+        let (report, same) = compare(["slow.ir", "wrong.ir"], &outcome(SLOW), &outcome(&wrong));
+        assert!(!same);
+        assert_eq!(
+            report.lines().nth(2),
+            Some("output: differs at line 2, a wrote 1 and b wrote 2")
+        );
+    }
+
+    #[test]
+    fn test_failed() {
+        let broken = FAST.replace("s := i * i", "s := i / #0");
+        let (report, same) = compare(["slow.ir", "broken.ir"], &outcome(SLOW), &outcome(&broken));
+        assert!(!same);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[2], "b failed: attempt to divide by zero");
+        assert_eq!(
+            lines[3],
+            "output: differs at line 1, a wrote 0 and b wrote nothing"
+        );
+        assert_eq!(lines[5], "        35          -        -35  -100.0%  total");
+    }
+
+    #[test]
+    fn test_private() {
+        let lib = Parser::with_file(
+            "lib.ir",
+            "PRIVATE FUNCTION helper :
+PARAM n
+RETURN n
+
+FUNCTION twice :
+PARAM n
+h := CALL helper(n)
+RETURN h
+",
+        )
+        .parse();
+        let main = Parser::with_file(
+            "main.ir",
+            "PRIVATE FUNCTION helper :
+PARAM n
+r := n + #1
+RETURN r
+
+FUNCTION main :
+a := CALL helper(#1)
+b := CALL twice(a)
+WRITE b
+RETURN #0
+",
+        )
+        .parse();
+        let outcome = Outcome::run(
+            Program::link(vec![lib, main]),
+            "".as_bytes(),
+            ImplicitReturn::Error,
+        );
+        assert_eq!(outcome.count("helper (lib.ir)"), Some(2));
+        assert_eq!(outcome.count("helper (main.ir)"), Some(3));
+        assert_eq!(outcome.count("helper"), None);
+    }
+}
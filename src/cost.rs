@@ -0,0 +1,211 @@
+use std::{collections::HashMap, fs};
+
+use crate::{
+    env::Env,
+    exec::Hook,
+    instr::{ArithOp, Instr, Program},
+};
+
+/// Kinds of instructions a cost model can weigh, besides `ADD`, `SUB`, `MUL`
+/// and `DIV`, which weigh `ARITH` by its operator.
+const KINDS: [&str; 16] = [
+    "ASSIGN", "ARITH", "DEREF", "LOAD", "STORE", "LABEL", "GOTO", "IF", "RETURN", "DEC", "ARG",
+    "CALL", "PARAM", "READ", "WRITE", "ASSERT",
+];
+
+const OPS: [&str; 4] = ["ADD", "SUB", "MUL", "DIV"];
+
+/// Cost models that come with misri, by name.
+const PRESETS: [(&str, &str); 1] = [(
+    "nolabels",
+    "# every instruction counts as one, except labels
+LABEL 0
+",
+)];
+
+fn kind(instr: &Instr) -> &'static str {
+    match instr {
+        Instr::Assign(..) => "ASSIGN",
+        Instr::Arith(..) => "ARITH",
+        Instr::Deref(..) => "DEREF",
+        Instr::Load(..) => "LOAD",
+        Instr::Store(..) => "STORE",
+        Instr::Label(_) => "LABEL",
+        Instr::Goto { .. } => "GOTO",
+        Instr::Cond { .. } => "IF",
+        Instr::Return(_) => "RETURN",
+        Instr::Dec(..) => "DEC",
+        Instr::Arg(_) => "ARG",
+        Instr::Call { .. } => "CALL",
+        Instr::Param(_) => "PARAM",
+        Instr::Read(_) => "READ",
+        Instr::Write(_) => "WRITE",
+        Instr::Assert { .. } => "ASSERT",
+    }
+}
+
+fn op_kind(op: &ArithOp) -> &'static str {
+    match op {
+        ArithOp::Add => "ADD",
+        ArithOp::Sub => "SUB",
+        ArithOp::Mul => "MUL",
+        ArithOp::Div => "DIV",
+    }
+}
+
+/// Weights of instructions by kind, for counting some as more expensive
+/// than others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostModel {
+    /// Weight of the kinds not listed.
+    default: u64,
+    weights: HashMap<String, u64>,
+}
+
+impl CostModel {
+    /// Parses lines of `KIND WEIGHT`, where `KIND` is written like the
+    /// keyword of the instruction, or `default` for all others. `#` starts a
+    /// comment.
+    pub fn parse(text: &str) -> Result<CostModel, String> {
+        let mut model = CostModel {
+            default: 1,
+            weights: HashMap::new(),
+        };
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let (kind, weight) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => continue,
+                [kind, weight] => (kind, weight),
+                _ => return Err(format!("expected KIND WEIGHT at line {}", lineno + 1)),
+            };
+            let weight = weight
+                .parse()
+                .map_err(|_| format!("invalid weight {weight} at line {}", lineno + 1))?;
+            match kind {
+                "default" => model.default = weight,
+                kind if KINDS.contains(&kind) || OPS.contains(&kind) => {
+                    model.weights.insert(String::from(kind), weight);
+                }
+                kind => return Err(format!("unknown kind {kind} at line {}", lineno + 1)),
+            }
+        }
+        Ok(model)
+    }
+
+    /// Loads the preset named `name`, or else the file at that path.
+    pub fn load(name: &str) -> Result<CostModel, String> {
+        match PRESETS.iter().find(|(preset, _)| *preset == name) {
+            Some((_, text)) => CostModel::parse(text),
+            None => {
+                let text = fs::read_to_string(name).map_err(|err| format!("{name}: {err}"))?;
+                CostModel::parse(&text).map_err(|err| format!("{name}: {err}"))
+            }
+        }
+    }
+
+    pub fn weight(&self, instr: &Instr) -> u64 {
+        let by_op = match instr {
+            Instr::Arith(_, _, op, _) => self.weights.get(op_kind(op)),
+            _ => None,
+        };
+        by_op
+            .or_else(|| self.weights.get(kind(instr)))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Adds up the weights of the instructions executed.
+pub struct Cost {
+    /// Weight of every instruction, by function and pc.
+    weights: Vec<Vec<u64>>,
+    total: u64,
+    /// Weight of the instruction being executed.
+    pending: u64,
+}
+
+impl Cost {
+    pub fn new(program: &Program, model: &CostModel) -> Self {
+        Cost {
+            weights: program
+                .funcs
+                .iter()
+                .map(|func| func.body.iter().map(|instr| model.weight(instr)).collect())
+                .collect(),
+            total: 0,
+            pending: 0,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+impl Hook for Cost {
+    fn before(&mut self, _program: &Program, env: &Env) {
+        let frame = env.top_frame();
+        self.pending = self.weights[frame.func]
+            .get(frame.pc)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn after(&mut self, _program: &Program, _env: &Env) {
+        self.total += self.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::tests::run_with;
+
+    use super::*;
+
+    fn cost(model: &CostModel) -> (u64, usize) {
+        let (_, cost, instr_cnt) = run_with(
+            "FUNCTION main :
+             i := #0
+             LABEL loop :
+             IF i >= #3 GOTO end
+             i := i + #1
+             j := i * #2
+             GOTO loop
+             LABEL end :
+             RETURN #0",
+            |program| Cost::new(program, model),
+        );
+        (cost.total(), instr_cnt)
+    }
+
+    #[test]
+    fn test_cost() {
+        assert_eq!(cost(&CostModel::parse("").unwrap()), (19, 19));
+        assert_eq!(cost(&CostModel::load("nolabels").unwrap()), (14, 19));
+        let model = CostModel::parse(
+            "# memory and calls are slow
+             default 2
+             LABEL 0   # free
+             ARITH 3
+             MUL 10",
+        )
+        .unwrap();
+        assert_eq!(cost(&model), (2 + 4 * 2 + 3 * 3 + 3 * 10 + 3 * 2, 19));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            CostModel::parse("CALL 2\nJUMP 1"),
+            Err(String::from("unknown kind JUMP at line 2"))
+        );
+        assert_eq!(
+            CostModel::parse("CALL two"),
+            Err(String::from("invalid weight two at line 1"))
+        );
+        assert_eq!(
+            CostModel::parse("CALL"),
+            Err(String::from("expected KIND WEIGHT at line 1"))
+        );
+    }
+}
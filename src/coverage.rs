@@ -0,0 +1,314 @@
+use crate::{
+    env::Env,
+    exec::Hook,
+    instr::{Func, Instr, Program},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageFormat {
+    /// A summary per function, listing what never ran.
+    Text,
+    /// An lcov tracefile, mapped to source lines.
+    Lcov,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FuncCoverage {
+    calls: usize,
+    /// Times each instruction of the body ran.
+    counts: Vec<usize>,
+    /// Times each `IF` of the body jumped and fell through.
+    edges: Vec<[usize; 2]>,
+}
+
+impl FuncCoverage {
+    fn conds<'a>(&'a self, func: &'a Func) -> impl Iterator<Item = (usize, &'a [usize; 2])> {
+        func.body
+            .iter()
+            .enumerate()
+            .filter(|(_, instr)| matches!(instr, Instr::Cond { .. }))
+            .map(|(pc, _)| (pc, &self.edges[pc]))
+    }
+
+    fn summary(&self, func: &Func) -> ([usize; 2], [usize; 2]) {
+        let ran = self.counts.iter().filter(|&&count| count > 0).count();
+        let edges: Vec<usize> = self.conds(func).flat_map(|(_, edges)| *edges).collect();
+        let taken = edges.iter().filter(|&&count| count > 0).count();
+        ([ran, self.counts.len()], [taken, edges.len()])
+    }
+}
+
+fn ratio([hit, total]: [usize; 2]) -> String {
+    match total {
+        0 => format!("{hit}/{total}"),
+        total => format!("{hit}/{total} ({:.1}%)", hit as f64 * 100.0 / total as f64),
+    }
+}
+
+/// Records which instructions ran and which ways every `IF` went.
+pub struct Coverage {
+    funcs: Vec<FuncCoverage>,
+    /// Instruction being executed, between `before` and `after`, and whether
+    /// it jumps if it is an `IF`.
+    pending: Option<(usize, usize, Option<bool>)>,
+}
+
+impl Coverage {
+    pub fn new(program: &Program) -> Self {
+        let mut funcs: Vec<FuncCoverage> = program
+            .funcs
+            .iter()
+            .map(|func| FuncCoverage {
+                calls: 0,
+                counts: vec![0; func.body.len()],
+                edges: vec![[0, 0]; func.body.len()],
+            })
+            .collect();
+        funcs[program.entry].calls = 1;
+        Coverage {
+            funcs,
+            pending: None,
+        }
+    }
+
+    pub fn report(&self, program: &Program, format: CoverageFormat) -> String {
+        match format {
+            CoverageFormat::Text => self.text(program),
+            CoverageFormat::Lcov => self.lcov(program),
+        }
+    }
+
+    fn text(&self, program: &Program) -> String {
+        let mut lines = Vec::new();
+        let mut total = ([0, 0], [0, 0]);
+        for func in program.funcs.iter().filter(|func| func.builtin.is_none()) {
+            let cov = &self.funcs[func.id];
+            let (instrs, branches) = cov.summary(func);
+            for i in 0..2 {
+                total.0[i] += instrs[i];
+                total.1[i] += branches[i];
+            }
+            let summary = format!(
+                "{} ({}): {} instructions, {} branches",
+                func.name,
+                program.file(func),
+                ratio(instrs),
+                ratio(branches)
+            );
+            if cov.calls == 0 {
+                lines.push(summary + ", never called");
+                continue;
+            }
+            lines.push(summary);
+            for (pc, instr) in func.body.iter().enumerate() {
+                let missed = match (cov.counts[pc], cov.edges[pc]) {
+                    (0, _) => "never ran",
+                    (_, [0, _]) if matches!(instr, Instr::Cond { .. }) => "never jumps",
+                    (_, [_, 0]) if matches!(instr, Instr::Cond { .. }) => "never falls through",
+                    _ => continue,
+                };
+                lines.push(format!(
+                    "    {}:{}  {instr}  {missed}",
+                    program.file(func),
+                    func.lines.get(pc).copied().unwrap_or_default()
+                ));
+            }
+        }
+        lines.push(format!(
+            "total: {} instructions, {} branches",
+            ratio(total.0),
+            ratio(total.1)
+        ));
+        lines.join("\n") + "\n"
+    }
+
+    fn lcov(&self, program: &Program) -> String {
+        let mut lines = vec![String::from("TN:")];
+        for (file, path) in program.files.iter().enumerate() {
+            let funcs: Vec<&Func> = program
+                .funcs
+                .iter()
+                .filter(|func| func.file == file && func.builtin.is_none())
+                .collect();
+            lines.push(format!("SF:{path}"));
+            for func in &funcs {
+                let line = func.lines.first().copied().unwrap_or(1);
+                lines.push(format!("FN:{line},{}", func.name));
+            }
+            for func in &funcs {
+                lines.push(format!("FNDA:{},{}", self.funcs[func.id].calls, func.name));
+            }
+            lines.push(format!("FNF:{}", funcs.len()));
+            let called = funcs.iter().filter(|func| self.funcs[func.id].calls > 0);
+            lines.push(format!("FNH:{}", called.count()));
+
+            // Instructions on the same line, such as the `ARG`s of an inline
+            // argument list, count as the line once, by the most run of them.
+            let mut counts: Vec<(usize, usize)> = Vec::new();
+            let mut branches = Vec::new();
+            for func in &funcs {
+                let cov = &self.funcs[func.id];
+                for (pc, &line) in func.lines.iter().enumerate() {
+                    match counts.iter_mut().find(|(prev, _)| *prev == line) {
+                        Some((_, count)) => *count = (*count).max(cov.counts[pc]),
+                        None => counts.push((line, cov.counts[pc])),
+                    }
+                }
+                for (block, (pc, edges)) in cov.conds(func).enumerate() {
+                    for (branch, &count) in edges.iter().enumerate() {
+                        let taken = match cov.counts[pc] {
+                            0 => String::from("-"),
+                            _ => count.to_string(),
+                        };
+                        let line = func.lines[pc];
+                        branches.push((format!("BRDA:{line},{block},{branch},{taken}"), count));
+                    }
+                }
+            }
+            counts.sort();
+            for (line, count) in &counts {
+                lines.push(format!("DA:{line},{count}"));
+            }
+            lines.push(format!("LF:{}", counts.len()));
+            let hit = counts.iter().filter(|(_, count)| *count > 0);
+            lines.push(format!("LH:{}", hit.count()));
+            for (branch, _) in &branches {
+                lines.push(branch.clone())
+            }
+            lines.push(format!("BRF:{}", branches.len()));
+            let hit = branches.iter().filter(|(_, count)| *count > 0);
+            lines.push(format!("BRH:{}", hit.count()));
+            lines.push(String::from("end_of_record"));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn record(&mut self, program: &Program) {
+        let (func, pc, jumps) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let cov = &mut self.funcs[func];
+        if let Some(count) = cov.counts.get_mut(pc) {
+            *count += 1
+        }
+        if let Some(jumps) = jumps {
+            cov.edges[pc][!jumps as usize] += 1
+        }
+        if let Some(Instr::Call { id, .. }) = program.funcs[func].body.get(pc) {
+            self.funcs[*id].calls += 1
+        }
+    }
+}
+
+impl Hook for Coverage {
+    fn before(&mut self, program: &Program, env: &Env) {
+        let frame = env.top_frame();
+        let instr = program.funcs[frame.func].body.get(frame.pc);
+        let jumps = instr.and_then(|instr| instr.jumps(env));
+        self.pending = Some((frame.func, frame.pc, jumps))
+    }
+
+    fn after(&mut self, program: &Program, _env: &Env) {
+        self.record(program)
+    }
+
+    fn stop(&mut self, program: &Program, _env: &Env) {
+        self.record(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::tests::run_with;
+
+    use super::*;
+
+    fn coverage(format: CoverageFormat) -> String {
+        let (program, coverage, _) = run_with(
+            "FUNCTION fact :
+PARAM n
+IF n > #1 GOTO rec
+RETURN #1
+LABEL rec :
+m := n - #1
+r := CALL fact(m)
+r := r * n
+RETURN r
+
+FUNCTION unused :
+IF #0 == #0 GOTO end
+LABEL end :
+RETURN #0
+
+FUNCTION main :
+x := CALL fact(#2)
+IF x >= #0 GOTO out
+WRITE x
+LABEL out :
+RETURN #0
+",
+            Coverage::new,
+        );
+        coverage.report(&program, format)
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            coverage(CoverageFormat::Text),
+            "fact (a.ir): 9/9 (100.0%) instructions, 2/2 (100.0%) branches
+unused (a.ir): 0/3 (0.0%) instructions, 0/2 (0.0%) branches, never called
+main (a.ir): 5/6 (83.3%) instructions, 1/2 (50.0%) branches
+    a.ir:18  IF x >= #0 GOTO out  never falls through
+    a.ir:19  WRITE x  never ran
+total: 14/18 (77.8%) instructions, 3/6 (50.0%) branches
+"
+        );
+    }
+
+    #[test]
+    fn test_lcov() {
+        assert_eq!(
+            coverage(CoverageFormat::Lcov),
+            "TN:
+SF:a.ir
+FN:2,fact
+FN:12,unused
+FN:17,main
+FNDA:2,fact
+FNDA:0,unused
+FNDA:1,main
+FNF:3
+FNH:2
+DA:2,2
+DA:3,2
+DA:4,1
+DA:5,1
+DA:6,1
+DA:7,1
+DA:8,1
+DA:9,1
+DA:12,0
+DA:13,0
+DA:14,0
+DA:17,1
+DA:18,1
+DA:19,0
+DA:20,1
+DA:21,1
+LF:16
+LH:12
+BRDA:3,0,0,1
+BRDA:3,0,1,1
+BRDA:12,0,0,-
+BRDA:12,0,1,-
+BRDA:18,0,0,1
+BRDA:18,0,1,0
+BRF:6
+BRH:3
+end_of_record
+"
+        );
+    }
+}
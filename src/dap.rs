@@ -0,0 +1,887 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Read, Write},
+    rc::Rc,
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    breakpoint::Location,
+    debug::{catch, show, Debugger},
+    exec::{ImplicitReturn, Interpreter},
+    expr::Expr,
+    instr::Program,
+    parser::Parser,
+    value::Value,
+};
+
+/// Variable references from this one on stand for pointers, those below for
+/// the frame at `reference - 1` in the stack.
+const POINTERS: usize = 1 << 20;
+
+/// Program output, shared with the server so that it can be forwarded as
+/// `output` events.
+#[derive(Debug, Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads one message framed by a `Content-Length` header, or `None` at the end
+/// of the input. A malformed message is skipped and returned as `Err`.
+fn read_message(input: &mut impl BufRead) -> Option<Result<Json, String>> {
+    let mut len = None;
+    let mut error = None;
+    loop {
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) => return None,
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                error = Some(String::from("header is not UTF-8"));
+                continue;
+            }
+            Err(_) => return None,
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                match value.trim().parse::<u64>() {
+                    Ok(value) => len = Some(value),
+                    Err(_) => error = Some(format!("invalid Content-Length: {}", value.trim())),
+                }
+            }
+        }
+    }
+    if let Some(error) = error {
+        return Some(Err(error));
+    }
+    let Some(len) = len else {
+        return Some(Err(String::from("missing Content-Length")));
+    };
+    let mut buf = Vec::new();
+    match input.take(len).read_to_end(&mut buf) {
+        Ok(n) if n as u64 == len => (),
+        _ => return None,
+    }
+    Some(serde_json::from_slice(&buf).map_err(|err| format!("invalid message: {err}")))
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(DIGITS[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+/// Start of the `DEC` object a pointer points into.
+fn base(ptr: &Value) -> Value {
+    match ptr {
+        Value::ValPtr { mem, size, .. } => Value::ValPtr {
+            mem: mem.clone(),
+            size: *size,
+            ptr: 0,
+        },
+        Value::ValInt(_) => panic!("{ptr} is not a pointer"),
+    }
+}
+
+/// A Debug Adapter Protocol server, driving a `Debugger` for an editor.
+pub struct Server<W: Write> {
+    out: W,
+    seq: usize,
+    debugger: Option<Debugger<Box<dyn Read>, Output>>,
+    output: Output,
+    stop_on_entry: bool,
+    /// Breakpoints set by `setBreakpoints`, by source path.
+    sources: HashMap<String, Vec<usize>>,
+    /// Breakpoints set by `setFunctionBreakpoints`.
+    functions: Vec<usize>,
+    /// Watchpoints set by `setDataBreakpoints`.
+    data: Vec<usize>,
+    /// Pointers handed out as variable and memory references since the last
+    /// stop.
+    pointers: Vec<Value>,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(out: W) -> Self {
+        Server {
+            out,
+            seq: 0,
+            debugger: None,
+            output: Output::default(),
+            stop_on_entry: false,
+            sources: HashMap::new(),
+            functions: Vec::new(),
+            data: Vec::new(),
+            pointers: Vec::new(),
+        }
+    }
+
+    /// Serves requests from `input` until it ends or the client disconnects.
+    pub fn run(&mut self, mut input: impl BufRead) {
+        while let Some(message) = read_message(&mut input) {
+            match message {
+                Ok(request) => {
+                    if !self.request(&request) {
+                        break;
+                    }
+                }
+                Err(error) => self.event(
+                    "output",
+                    json!({ "category": "console", "output": format!("error: {error}\n") }),
+                ),
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{body}", body.len()).expect("write error");
+        self.out.flush().expect("write error")
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn respond(&mut self, request: &Json, body: Result<Json, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(msg) => response["message"] = json!(msg),
+        }
+        self.send(response)
+    }
+
+    fn debugger(&self) -> Result<&Debugger<Box<dyn Read>, Output>, String> {
+        self.debugger
+            .as_ref()
+            .ok_or_else(|| String::from("no program launched"))
+    }
+
+    /// Handles one request, returning `false` when the session should end.
+    fn request(&mut self, request: &Json) -> bool {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        let body = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsDataBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+                "supportsStepBack": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "dataBreakpointInfo" => self.data_breakpoint_info(args),
+            "setDataBreakpoints" => self.set_data_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => Ok(json!({ "threads": [{ "id": 1, "name": "main" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => self.scopes(args),
+            "variables" => self.variables(args),
+            "evaluate" => self.evaluate(args),
+            "readMemory" => self.read_memory(args),
+            "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" | "stepBack"
+            | "reverseContinue" => match self.debugger() {
+                Ok(_) => {
+                    self.respond(request, Ok(json!({ "allThreadsContinued": true })));
+                    self.resume(command);
+                    return true;
+                }
+                Err(err) => Err(err),
+            },
+            "disconnect" => {
+                self.respond(request, Ok(json!({})));
+                return false;
+            }
+            _ => Err(format!("unsupported request: {command}")),
+        };
+        self.respond(request, body);
+        if command == "launch" && self.debugger.is_some() {
+            self.event("initialized", json!({}))
+        }
+        true
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        let files: Vec<String> = match &args["program"] {
+            Json::String(file) => vec![file.clone()],
+            Json::Array(files) => files
+                .iter()
+                .filter_map(|file| file.as_str())
+                .map(String::from)
+                .collect(),
+            _ => return Err(String::from("launch needs a program")),
+        };
+        let input: Box<dyn Read> = match args["input"].as_str() {
+            Some(file) => Box::new(fs::File::open(file).map_err(|err| format!("{file}: {err}"))?),
+            None => Box::new(io::empty()),
+        };
+        let mut programs = Vec::new();
+        for file in &files {
+            let cont = fs::read_to_string(file).map_err(|err| format!("{file}: {err}"))?;
+            programs.push(catch(|| Parser::with_file(file, cont.as_str()).parse())?);
+        }
+        let output = self.output.clone();
+        let mut interpreter = catch(|| Interpreter::new(Program::link(programs), input, output))?;
+        if args["implicitReturn"] == "zero" {
+            interpreter.set_implicit_return(ImplicitReturn::Zero)
+        }
+        for error in interpreter.program().check_args() {
+            self.event(
+                "output",
+                json!({ "category": "console", "output": format!("warning: {error}\n") }),
+            )
+        }
+        let mut debugger = Debugger::new(interpreter);
+        debugger.set_record(true);
+        self.debugger = Some(debugger);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or_default();
+        Ok(json!({}))
+    }
+
+    /// Replaces the breakpoints in `args.source` with one per requested line.
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or("setBreakpoints needs a source path")?;
+        let debugger = self.debugger.as_mut().ok_or("no program launched")?;
+        for id in self.sources.remove(path).unwrap_or_default() {
+            debugger.delete_breakpoint(id)?
+        }
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut ids = Vec::new();
+        let mut breakpoints = Vec::new();
+        for bp in &requested {
+            let line = bp["line"].as_u64().unwrap_or_default();
+            let cond = bp["condition"]
+                .as_str()
+                .filter(|cond| !cond.trim().is_empty());
+            let loc = Location::Line(Some(String::from(path)), line as usize);
+            let added = cond.map(Expr::parse).transpose().and_then(|_| {
+                let id = debugger.add_location(loc.clone())?;
+                debugger.set_condition(id, cond)?;
+                let program = debugger.interpreter().program();
+                let (func, pc) = loc.resolve(program)?[0];
+                Ok((id, program.funcs[func].lines[pc]))
+            });
+            breakpoints.push(match added {
+                Ok((id, line)) => {
+                    ids.push(id);
+                    json!({ "id": id, "verified": true, "line": line })
+                }
+                Err(msg) => json!({ "verified": false, "line": line, "message": msg }),
+            })
+        }
+        self.sources.insert(String::from(path), ids);
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_function_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let debugger = self.debugger.as_mut().ok_or("no program launched")?;
+        for id in self.functions.drain(..) {
+            debugger.delete_breakpoint(id)?
+        }
+        let names = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut breakpoints = Vec::new();
+        for name in names.iter().filter_map(|bp| bp["name"].as_str()) {
+            breakpoints.push(
+                match debugger.add_location(Location::Func(String::from(name))) {
+                    Ok(id) => {
+                        self.functions.push(id);
+                        json!({ "id": id, "verified": true })
+                    }
+                    Err(msg) => json!({ "verified": false, "message": msg }),
+                },
+            )
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Names the variable `args.name` of a frame as a watchpoint target, as
+    /// in `fib:n`. Cells of pointers cannot be watched this way.
+    fn data_breakpoint_info(&self, args: &Json) -> Result<Json, String> {
+        let name = args["name"]
+            .as_str()
+            .ok_or("dataBreakpointInfo needs a name")?;
+        let interpreter = self.debugger()?.interpreter();
+        let stack = interpreter.env().stack();
+        let depth = match args["variablesReference"].as_u64() {
+            Some(reference) => (reference as usize).wrapping_sub(1),
+            None => stack.len() - 1,
+        };
+        let info = match stack.get(depth) {
+            Some(frame) => {
+                let target = format!("{}:{name}", interpreter.program().funcs[frame.func].name);
+                json!({ "dataId": target, "description": target, "accessTypes": ["write"] })
+            }
+            None => json!({ "dataId": null, "description": format!("{name} cannot be watched") }),
+        };
+        Ok(info)
+    }
+
+    fn set_data_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let debugger = self.debugger.as_mut().ok_or("no program launched")?;
+        for id in self.data.drain(..) {
+            debugger.delete_breakpoint(id)?
+        }
+        let targets = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut breakpoints = Vec::new();
+        for target in targets.iter().filter_map(|bp| bp["dataId"].as_str()) {
+            breakpoints.push(match debugger.add_watchpoint(target, true) {
+                Ok(id) => {
+                    self.data.push(id);
+                    json!({ "id": id, "verified": true })
+                }
+                Err(msg) => json!({ "verified": false, "message": msg }),
+            })
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Frames from the innermost out, with the stack index as id.
+    fn stack_trace(&self) -> Result<Json, String> {
+        let interpreter = self.debugger()?.interpreter();
+        let program = interpreter.program();
+        let frames: Vec<Json> = interpreter
+            .env()
+            .stack()
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(depth, frame)| {
+                let func = &program.funcs[frame.func];
+                let path = program.file(func);
+                let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+                let line = func
+                    .lines
+                    .get(frame.pc)
+                    .or(func.lines.last())
+                    .copied()
+                    .unwrap_or_default();
+                json!({
+                    "id": depth,
+                    "name": func.name,
+                    "source": { "name": name, "path": path },
+                    "line": line,
+                    "column": 1,
+                })
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn scopes(&self, args: &Json) -> Result<Json, String> {
+        let depth = args["frameId"].as_u64().ok_or("scopes needs a frameId")? as usize;
+        Ok(json!({
+            "scopes": [{ "name": "Locals", "variablesReference": depth + 1, "expensive": false }]
+        }))
+    }
+
+    /// Describes a value as a variable, handing out a reference to pointers so
+    /// that their object can be expanded and read as memory.
+    fn variable(&mut self, name: &str, value: &Value) -> Json {
+        let mut var = json!({ "name": name, "value": show(value), "variablesReference": 0 });
+        if let Value::ValPtr { .. } = value {
+            self.pointers.push(value.clone());
+            let id = self.pointers.len() - 1;
+            var["variablesReference"] = json!(POINTERS + id);
+            var["memoryReference"] = json!(id.to_string());
+        }
+        var
+    }
+
+    fn variables(&mut self, args: &Json) -> Result<Json, String> {
+        let reference = args["variablesReference"]
+            .as_u64()
+            .ok_or("variables needs a reference")? as usize;
+        let interpreter = self.debugger()?.interpreter();
+        let vars: Vec<(String, Value)> = if reference >= POINTERS {
+            let ptr = self
+                .pointers
+                .get(reference - POINTERS)
+                .ok_or("stale variable reference")?;
+            let Value::ValPtr { size, .. } = ptr else {
+                unreachable!()
+            };
+            (0..*size)
+                .step_by(4)
+                .map_while(|offset| {
+                    let int = (base(ptr) + Value::new_int(offset as i64)).peek()?;
+                    Some((format!("[{offset}]"), Value::new_int(int)))
+                })
+                .collect()
+        } else {
+            let frame = interpreter
+                .env()
+                .stack()
+                .get(reference.wrapping_sub(1))
+                .ok_or("no such frame")?;
+            interpreter
+                .program()
+                .vars(frame)
+                .into_iter()
+                .map(|(name, value)| (String::from(name), value.clone()))
+                .collect()
+        };
+        let vars: Vec<Json> = vars
+            .iter()
+            .map(|(name, value)| self.variable(name, value))
+            .collect();
+        Ok(json!({ "variables": vars }))
+    }
+
+    fn evaluate(&mut self, args: &Json) -> Result<Json, String> {
+        let expr = args["expression"]
+            .as_str()
+            .ok_or("evaluate needs an expression")?
+            .trim();
+        let debugger = self.debugger()?;
+        let top = debugger.interpreter().env().stack().len() - 1;
+        let depth = args["frameId"].as_u64().map_or(top, |depth| depth as usize);
+        let value = debugger.eval(expr, depth)?;
+        let var = self.variable(expr, &value);
+        Ok(json!({
+            "result": var["value"],
+            "variablesReference": var["variablesReference"],
+            "memoryReference": var["memoryReference"],
+        }))
+    }
+
+    /// Reads the `DEC` object behind a memory reference, one 4-byte
+    /// little-endian word per cell.
+    fn read_memory(&self, args: &Json) -> Result<Json, String> {
+        let reference = args["memoryReference"]
+            .as_str()
+            .ok_or("readMemory needs a memoryReference")?;
+        let ptr = reference
+            .parse()
+            .ok()
+            .and_then(|id: usize| self.pointers.get(id))
+            .ok_or("stale memory reference")?;
+        let offset = args["offset"].as_i64().unwrap_or_default().max(0) as usize;
+        let count = args["count"].as_u64().unwrap_or_default() as usize;
+        let Value::ValPtr { size, .. } = ptr else {
+            unreachable!()
+        };
+        let end = offset.saturating_add(count).min(*size);
+        let bytes: Vec<u8> = (offset..end)
+            .map(|addr| {
+                let cell = base(ptr) + Value::new_int((addr & !3) as i64);
+                (cell.peek().unwrap_or_default() as i32).to_le_bytes()[addr & 3]
+            })
+            .collect();
+        Ok(json!({
+            "address": offset.to_string(),
+            "data": encode_base64(&bytes),
+            "unreadableBytes": count - bytes.len(),
+        }))
+    }
+
+    /// Runs the program as `command` asks and tells the client where it
+    /// stopped.
+    fn resume(&mut self, command: &str) {
+        let debugger = self.debugger.as_mut().unwrap();
+        let reason = match command {
+            "configurationDone" if self.stop_on_entry => {
+                debugger.stop_at_entry();
+                "entry"
+            }
+            _ if command != "stepBack" && command != "reverseContinue" && !debugger.running() => {
+                return self.event("terminated", json!({}));
+            }
+            "next" => {
+                debugger.next();
+                "step"
+            }
+            "stepIn" => {
+                debugger.step();
+                "step"
+            }
+            "stepOut" => {
+                debugger.finish();
+                "step"
+            }
+            "stepBack" => {
+                debugger.reverse_step();
+                "step"
+            }
+            "reverseContinue" => {
+                debugger.reverse_cont();
+                "step"
+            }
+            _ => {
+                debugger.cont();
+                "pause"
+            }
+        };
+        self.pointers.clear();
+        let program_output = String::from_utf8_lossy(&self.output.0.take()).into_owned();
+        if !program_output.is_empty() {
+            self.event(
+                "output",
+                json!({ "category": "stdout", "output": program_output }),
+            )
+        }
+        let debugger = self.debugger.as_mut().unwrap();
+        let report = debugger.take_report();
+        let (error, hit, done) = (
+            debugger.error().map(String::from),
+            debugger.hit(),
+            !debugger.running(),
+        );
+        let reason = match debugger.watch_hit() {
+            true => "data breakpoint",
+            false => reason,
+        };
+        let exit_code = debugger.interpreter().exit_code();
+        for line in report {
+            self.event(
+                "output",
+                json!({ "category": "console", "output": line + "\n" }),
+            )
+        }
+        match (error, hit) {
+            (Some(msg), _) => self.event(
+                "stopped",
+                json!({ "reason": "exception", "description": msg, "text": msg, "threadId": 1 }),
+            ),
+            (None, _) if done => {
+                self.event("exited", json!({ "exitCode": exit_code }));
+                self.event("terminated", json!({}))
+            }
+            (None, Some(id)) => self.event(
+                "stopped",
+                json!({ "reason": "breakpoint", "hitBreakpointIds": [id], "threadId": 1 }),
+            ),
+            (None, None) => self.event("stopped", json!({ "reason": reason, "threadId": 1 })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn frame(request: Json) -> String {
+        let body = request.to_string();
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    /// Runs a scripted client, numbering its requests and returning every
+    /// message the server sends.
+    fn session(name: &str, code: &str, input: &str, requests: Vec<Json>) -> Vec<Json> {
+        let path = env::temp_dir().join(format!("misri-dap-{name}.ir"));
+        let input_path = env::temp_dir().join(format!("misri-dap-{name}.in"));
+        fs::write(&path, code).unwrap();
+        fs::write(&input_path, input).unwrap();
+        let launch = json!({
+            "command": "launch",
+            "arguments": { "program": path, "input": input_path },
+        });
+        let script: String = [json!({ "command": "initialize" }), launch]
+            .into_iter()
+            .chain(requests)
+            .enumerate()
+            .map(|(seq, mut request)| {
+                request["seq"] = json!(seq + 1);
+                request["type"] = json!("request");
+                if let Some(args) = request["arguments"].as_object_mut() {
+                    if args.get("source").is_some() {
+                        args["source"] = json!({ "path": path });
+                    }
+                }
+                frame(request)
+            })
+            .collect();
+        let mut out = Vec::new();
+        Server::new(&mut out).run(script.as_bytes());
+        let mut out = out.as_slice();
+        std::iter::from_fn(|| read_message(&mut out))
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    /// Finds the body of the response to the `nth` request with `command`.
+    fn response<'a>(messages: &'a [Json], command: &str, nth: usize) -> &'a Json {
+        messages
+            .iter()
+            .filter(|msg| msg["type"] == "response" && msg["command"] == command)
+            .nth(nth)
+            .map(|msg| &msg["body"])
+            .unwrap()
+    }
+
+    fn events<'a>(messages: &'a [Json], event: &'a str) -> impl Iterator<Item = &'a Json> {
+        messages
+            .iter()
+            .filter(move |msg| msg["event"] == event)
+            .map(|msg| &msg["body"])
+    }
+
+    const FIB: &str = "FUNCTION fib :
+PARAM n
+IF n > #1 GOTO rec
+RETURN n
+LABEL rec :
+t1 := n - #1
+ARG t1
+r1 := CALL fib
+t2 := n - #2
+ARG t2
+r2 := CALL fib
+u := r1 + r2
+RETURN u
+
+FUNCTION main :
+READ n
+DEC arr 8
+p := &arr
+*p := n
+ARG n
+s := CALL fib
+WRITE s
+RETURN #0
+";
+
+    #[test]
+    fn test_breakpoints() {
+        let messages = session(
+            "breakpoints",
+            FIB,
+            "2\n",
+            vec![
+                json!({ "command": "setBreakpoints", "arguments": { "source": {}, "breakpoints": [{ "line": 4 }, { "line": 14 }, { "line": 12, "condition": "n ==" }] } }),
+                json!({ "command": "configurationDone" }),
+                json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+                json!({ "command": "variables", "arguments": { "variablesReference": 3 } }),
+                json!({ "command": "stepOut" }),
+                json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+                json!({ "command": "continue" }),
+                json!({ "command": "continue" }),
+                json!({ "command": "disconnect" }),
+            ],
+        );
+        let bps = &response(&messages, "setBreakpoints", 0)["breakpoints"];
+        assert_eq!(bps[0]["verified"], true);
+        assert_eq!(bps[0]["line"], 4);
+        assert_eq!(bps[1]["verified"], false);
+        assert_eq!(bps[2]["message"], "unexpected TokEOF");
+
+        let frames = &response(&messages, "stackTrace", 0)["stackFrames"];
+        let frames: Vec<(&Json, &Json)> = frames
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| (&f["name"], &f["line"]))
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                (&json!("fib"), &json!(4)),
+                (&json!("fib"), &json!(8)),
+                (&json!("main"), &json!(21))
+            ]
+        );
+
+        let vars = &response(&messages, "variables", 0)["variables"];
+        assert_eq!(
+            vars[0],
+            json!({ "name": "n", "value": "1", "variablesReference": 0 })
+        );
+
+        let vars = &response(&messages, "variables", 1)["variables"];
+        assert_eq!(vars[0]["name"], "n");
+        assert_eq!(vars[0]["value"], "2");
+        assert_eq!(vars[2]["name"], "r1");
+        assert_eq!(vars[2]["value"], "1");
+
+        let stops: Vec<&Json> = events(&messages, "stopped")
+            .map(|stop| &stop["reason"])
+            .collect();
+        assert_eq!(stops, ["breakpoint", "step", "breakpoint"]);
+        let output: Vec<&Json> = events(&messages, "output")
+            .map(|out| &out["output"])
+            .collect();
+        assert_eq!(output, ["1\n"]);
+        assert_eq!(events(&messages, "terminated").count(), 1);
+    }
+
+    #[test]
+    fn test_memory() {
+        let messages = session(
+            "memory",
+            FIB,
+            "5\n",
+            vec![
+                json!({ "command": "setFunctionBreakpoints", "arguments": { "breakpoints": [{ "name": "fib" }] } }),
+                json!({ "command": "configurationDone" }),
+                json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+                json!({ "command": "variables", "arguments": { "variablesReference": POINTERS + 1 } }),
+                json!({ "command": "readMemory", "arguments": { "memoryReference": "0", "offset": 0, "count": 12 } }),
+                json!({ "command": "readMemory", "arguments": { "memoryReference": "0", "offset": 4, "count": u64::MAX } }),
+                json!({ "command": "evaluate", "arguments": { "expression": "n", "frameId": 0 } }),
+                json!({ "command": "stepBack" }),
+                json!({ "command": "evaluate", "arguments": { "expression": "s" } }),
+                json!({ "command": "evaluate", "arguments": { "expression": "*(p + 4) - n" } }),
+                json!({ "command": "disconnect" }),
+            ],
+        );
+        let vars = &response(&messages, "variables", 0)["variables"];
+        assert_eq!(vars[1]["name"], "arr");
+        assert_eq!(vars[1]["value"], "ptr+0 (8 bytes)");
+        assert_eq!(vars[1]["memoryReference"], "0");
+
+        let cells = &response(&messages, "variables", 1)["variables"];
+        assert_eq!(
+            cells,
+            &json!([
+                { "name": "[0]", "value": "5", "variablesReference": 0 },
+                { "name": "[4]", "value": "0", "variablesReference": 0 },
+            ])
+        );
+
+        let memory = response(&messages, "readMemory", 0);
+        assert_eq!(memory["data"], encode_base64(&[5, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(memory["unreadableBytes"], 4);
+        let memory = response(&messages, "readMemory", 1);
+        assert_eq!(memory["data"], encode_base64(&[0, 0, 0, 0]));
+
+        assert_eq!(response(&messages, "evaluate", 0)["result"], "5");
+        assert_eq!(response(&messages, "evaluate", 1)["result"], "0");
+        assert_eq!(response(&messages, "evaluate", 2)["result"], "-5");
+    }
+
+    #[test]
+    fn test_data_breakpoints() {
+        let messages = session(
+            "data",
+            "FUNCTION main :
+READ n
+s := n + #1
+ARG s
+u := CALL exit
+RETURN #0
+",
+            "2\n",
+            vec![
+                json!({ "command": "dataBreakpointInfo", "arguments": { "name": "s", "variablesReference": 1 } }),
+                json!({ "command": "setDataBreakpoints", "arguments": { "breakpoints": [{ "dataId": "main:s" }, { "dataId": "main:nope" }] } }),
+                json!({ "command": "configurationDone" }),
+                json!({ "command": "continue" }),
+                json!({ "command": "disconnect" }),
+            ],
+        );
+        assert_eq!(
+            response(&messages, "dataBreakpointInfo", 0)["dataId"],
+            "main:s"
+        );
+        let bps = &response(&messages, "setDataBreakpoints", 0)["breakpoints"];
+        assert_eq!(bps[0]["verified"], true);
+        assert_eq!(bps[1]["verified"], false);
+        let stops: Vec<&Json> = events(&messages, "stopped")
+            .map(|stop| &stop["reason"])
+            .collect();
+        assert_eq!(stops, ["data breakpoint"]);
+        let exited: Vec<&Json> = events(&messages, "exited")
+            .map(|exited| &exited["exitCode"])
+            .collect();
+        assert_eq!(exited, [3]);
+    }
+
+    #[test]
+    fn test_breakpoint_at_entry() {
+        let messages = session(
+            "entry",
+            FIB,
+            "2\n",
+            vec![
+                json!({ "command": "setBreakpoints", "arguments": { "source": {}, "breakpoints": [{ "line": 16 }] } }),
+                json!({ "command": "configurationDone" }),
+                json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+                json!({ "command": "continue" }),
+                json!({ "command": "disconnect" }),
+            ],
+        );
+        let stops: Vec<&Json> = events(&messages, "stopped")
+            .map(|stop| &stop["reason"])
+            .collect();
+        assert_eq!(stops, ["breakpoint"]);
+        assert_eq!(events(&messages, "terminated").count(), 1);
+        let frames = &response(&messages, "stackTrace", 0)["stackFrames"];
+        assert_eq!(frames[0]["line"], 16);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let script = [
+            String::from("Content-Length: x\r\n\r\n"),
+            String::from("Content-Length: 3\r\n\r\n{\"a"),
+            frame(json!({ "seq": 1, "type": "request", "command": "initialize" })),
+        ]
+        .concat();
+        let mut out = Vec::new();
+        Server::new(&mut out).run(script.as_bytes());
+        let mut out = out.as_slice();
+        let messages: Vec<Json> = std::iter::from_fn(|| read_message(&mut out))
+            .map(Result::unwrap)
+            .collect();
+        let errors: Vec<&str> = events(&messages, "output")
+            .filter_map(|out| out["output"].as_str())
+            .collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("error: invalid Content-Length: x"));
+        assert!(errors[1].starts_with("error: invalid message"));
+        assert_eq!(
+            response(&messages, "initialize", 0)["supportsStepBack"],
+            true
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,1171 @@
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    fmt::Debug,
+    io::{BufRead, Read, Write},
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use crate::{
+    breakpoint::{Breakpoints, Location, Target},
+    env::Effect,
+    exec::Interpreter,
+    expr::{Expr, Scope},
+    value::Value,
+};
+
+thread_local! {
+    /// Whether this thread is inside `catch`, whose panics are not printed.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Installs, once for all threads, a panic hook that stays silent inside
+/// `catch`.
+static HOOK: Once = Once::new();
+
+/// Runs `f`, turning a panic of the interpreter into its message.
+pub fn catch<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    HOOK.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.get() {
+                hook(info)
+            }
+        }))
+    });
+    let catching = CATCHING.replace(true);
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.set(catching);
+    result.map_err(|err| {
+        err.downcast_ref::<String>()
+            .cloned()
+            .or_else(|| err.downcast_ref::<&str>().map(|msg| msg.to_string()))
+            .unwrap_or_default()
+    })
+}
+
+/// Instructions kept in the history for reverse execution, the oldest being
+/// dropped first.
+const HISTORY: usize = 100_000;
+
+/// Formats a value for the debugger, showing pointers by offset and size.
+pub fn show(value: &Value) -> String {
+    match value {
+        Value::ValInt(int) => format!("{}", *int as i32),
+        Value::ValPtr { size, ptr, .. } => format!("ptr+{} ({size} bytes)", *ptr as i64),
+    }
+}
+
+fn parse(int: &str) -> Result<usize, String> {
+    int.parse().map_err(|_| format!("invalid number: {int}"))
+}
+
+/// What follows the first word of `line`.
+fn rest(line: &str) -> Option<&str> {
+    line.trim()
+        .split_once(char::is_whitespace)
+        .map(|(_, rest)| rest.trim())
+}
+
+pub struct Debugger<T, U>
+where
+    U: Write,
+{
+    interpreter: Interpreter<T, U>,
+    /// Message of the runtime error that stopped the program, if any.
+    error: Option<String>,
+    breakpoints: Breakpoints,
+    /// Breakpoint the program last stopped at.
+    hit: Option<usize>,
+    /// Whether the program last stopped at a watchpoint.
+    watch_hit: bool,
+    /// Messages about watchpoint hits since the last command.
+    report: Vec<String>,
+    /// Effects of the last instructions executed, while recording for
+    /// reverse execution.
+    history: Option<VecDeque<Vec<Effect>>>,
+    /// Most instructions kept in `history`.
+    history_limit: usize,
+    /// Whether the program moved since the last command started.
+    moved: bool,
+    /// Whether the program was resumed before, so that breakpoints at the
+    /// first instruction were already checked.
+    started: bool,
+    /// Breakpoint whose commands are being typed, with those typed so far.
+    defining: Option<(usize, Vec<String>)>,
+}
+
+impl<T, U> Debugger<T, U>
+where
+    T: Read,
+    U: Write + Debug,
+{
+    pub fn new(interpreter: Interpreter<T, U>) -> Self {
+        Debugger {
+            interpreter,
+            error: None,
+            breakpoints: Breakpoints::new(),
+            hit: None,
+            watch_hit: false,
+            report: Vec::new(),
+            history: None,
+            history_limit: HISTORY,
+            moved: false,
+            started: false,
+            defining: None,
+        }
+    }
+
+    /// Starts or stops recording history for reverse execution.
+    /// History recorded so far is kept when recording is already on.
+    pub fn set_record(&mut self, on: bool) {
+        let history = self.history.take();
+        self.history = on.then(|| history.unwrap_or_default());
+        let watching = self.breakpoints.watches().next().is_some();
+        self.interpreter.env_mut().record(on || watching)
+    }
+
+    pub fn instr_cnt(&self) -> usize {
+        self.interpreter.instr_cnt()
+    }
+
+    pub fn interpreter(&self) -> &Interpreter<T, U> {
+        &self.interpreter
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    /// Message of the runtime error that stopped the program, if any.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Breakpoint the program last stopped at.
+    pub fn hit(&self) -> Option<usize> {
+        self.hit
+    }
+
+    /// Whether the program last stopped at a watchpoint.
+    pub fn watch_hit(&self) -> bool {
+        self.watch_hit
+    }
+
+    /// Takes the messages about watchpoint hits since the last call.
+    pub fn take_report(&mut self) -> Vec<String> {
+        self.report.drain(..).collect()
+    }
+
+    /// Adds a breakpoint written as `loc` or `loc if cond`.
+    pub fn add_breakpoint(&mut self, spec: &str) -> Result<usize, String> {
+        let (loc, cond) = match spec.split_once(" if ") {
+            Some((loc, cond)) => (loc.trim(), Some(cond.trim())),
+            None => (spec, None),
+        };
+        cond.map(Expr::parse).transpose()?;
+        let id = self.add_location(Location::parse(loc))?;
+        self.set_condition(id, cond)?;
+        Ok(id)
+    }
+
+    pub fn add_location(&mut self, loc: Location) -> Result<usize, String> {
+        self.breakpoints.add(self.interpreter.program(), loc)
+    }
+
+    pub fn set_condition(&mut self, id: usize, cond: Option<&str>) -> Result<(), String> {
+        self.breakpoints.get_mut(id)?.set_cond(cond)
+    }
+
+    pub fn delete_breakpoint(&mut self, id: usize) -> Result<(), String> {
+        self.breakpoints.delete(id)
+    }
+
+    /// Watches `target`, stopping at every change if `stop` is set and only
+    /// reporting it otherwise.
+    pub fn add_watchpoint(&mut self, target: &str, stop: bool) -> Result<usize, String> {
+        let parsed = Target::parse(target, self.interpreter.program(), self.interpreter.env())?;
+        self.interpreter.env_mut().record(true);
+        Ok(self.breakpoints.watch(target, parsed, stop))
+    }
+
+    /// Reports watchpoint hits caused by the instruction at `(func, pc)` and
+    /// keeps its effects in the history, returning whether any hit stops.
+    fn check_watches(&mut self, func: usize, pc: usize) -> bool {
+        let effects = self.interpreter.env_mut().effects();
+        let changes = self.breakpoints.changes(self.interpreter.env(), &effects);
+        self.remember(effects);
+        if changes.is_empty() {
+            return false;
+        }
+        let program = self.interpreter.program();
+        let instr = program.funcs[func]
+            .body
+            .get(pc)
+            .map_or(String::from("<end of function>"), |instr| instr.to_string());
+        for change in &changes {
+            let name = &self
+                .breakpoints
+                .watches()
+                .find(|wp| wp.id == change.id)
+                .unwrap()
+                .name;
+            self.report.push(format!(
+                "Watchpoint {}, {name}: {} -> {} by {}  {instr}",
+                change.id,
+                show(&change.old),
+                show(&change.new),
+                program.loc_at(func, pc)
+            ))
+        }
+        changes.iter().any(|change| change.stop)
+    }
+
+    pub fn running(&self) -> bool {
+        !self.interpreter.done() && self.error.is_none()
+    }
+
+    fn depth(&self) -> usize {
+        self.interpreter.env().stack().len()
+    }
+
+    /// Finds the breakpoint to stop at before the next instruction, counting
+    /// a hit of every breakpoint there if `count` is set. A condition that
+    /// cannot be evaluated is reported and stops.
+    fn breakpoint(&mut self, count: bool) -> Option<usize> {
+        let env = self.interpreter.env();
+        let frame = env.top_frame();
+        let scope = Scope {
+            program: self.interpreter.program(),
+            env,
+            frame,
+            icount: self.interpreter.instr_cnt(),
+        };
+        let report = &mut self.report;
+        let holds = |id, expr: &Expr| {
+            expr.holds(&scope).unwrap_or_else(|err| {
+                report.push(format!("error in condition of breakpoint {id}: {err}"));
+                true
+            })
+        };
+        match count {
+            true => self.breakpoints.hit(frame.func, frame.pc, holds),
+            false => self.breakpoints.at(frame.func, frame.pc, holds),
+        }
+    }
+
+    /// Runs one instruction at a time while `cond` holds, stopping early at
+    /// breakpoints.
+    fn run_while(&mut self, mut cond: impl FnMut(&Self) -> bool) {
+        self.hit = None;
+        self.watch_hit = false;
+        self.moved = true;
+        self.started = true;
+        let result = catch(|| loop {
+            let frame = self.interpreter.env().top_frame();
+            let (func, pc) = (frame.func, frame.pc);
+            let running = self.interpreter.tick();
+            let watched = self.check_watches(func, pc);
+            if !running {
+                break;
+            }
+            self.hit = self.breakpoint(true);
+            self.watch_hit = watched;
+            if watched || self.hit.is_some() || !cond(self) {
+                break;
+            }
+        });
+        self.interpreter.flush();
+        if let Err(msg) = result {
+            let effects = self.interpreter.env_mut().effects();
+            self.remember(effects);
+            self.error = Some(msg)
+        }
+    }
+
+    /// Keeps `effects` in the history if recording, dropping the oldest
+    /// instruction beyond the limit.
+    fn remember(&mut self, effects: Vec<Effect>) {
+        if let Some(history) = &mut self.history {
+            history.push_back(effects);
+            if history.len() > self.history_limit {
+                history.pop_front();
+            }
+        }
+    }
+
+    /// Moves back to before the last instruction executed, returning `false`
+    /// if there is no recorded history left.
+    pub fn reverse_step(&mut self) -> bool {
+        let effects = match self.history.as_mut().and_then(|history| history.pop_back()) {
+            Some(effects) => effects,
+            None => return false,
+        };
+        self.interpreter.undo(effects);
+        self.error = None;
+        self.hit = None;
+        self.watch_hit = false;
+        self.moved = true;
+        true
+    }
+
+    /// Moves back until a breakpoint is reached, or until right before an
+    /// instruction that changed a watched variable.
+    pub fn reverse_cont(&mut self) {
+        self.watch_hit = false;
+        self.moved = true;
+        loop {
+            let effects = match self.history.as_mut().and_then(|history| history.pop_back()) {
+                Some(effects) => effects,
+                None => {
+                    self.report
+                        .push(String::from("reached the start of the recording"));
+                    break;
+                }
+            };
+            let watched = self
+                .breakpoints
+                .watched(self.interpreter.env(), &effects)
+                .map(|wp| format!("Watchpoint {}, {} is changed by", wp.id, wp.name));
+            self.interpreter.undo(effects);
+            self.error = None;
+            self.hit = self.breakpoint(false);
+            if let Some(watched) = watched {
+                self.report.push(watched);
+                self.watch_hit = true;
+                break;
+            }
+            if self.hit.is_some() {
+                break;
+            }
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.run_while(|_| false)
+    }
+
+    /// Steps over calls, stopping at the next instruction of the current frame
+    /// or of its caller.
+    pub fn next(&mut self) {
+        let depth = self.depth();
+        self.run_while(|debugger| debugger.depth() > depth)
+    }
+
+    /// Runs until the current function returns.
+    pub fn finish(&mut self) {
+        let depth = self.depth();
+        self.run_while(|debugger| debugger.depth() >= depth)
+    }
+
+    /// Counts the program as stopped at its first instruction already, so
+    /// that `cont` leaves it instead of stopping at a breakpoint there.
+    pub fn stop_at_entry(&mut self) {
+        self.started = true
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit. The first time, this
+    /// includes a breakpoint at the instruction the program starts at.
+    pub fn cont(&mut self) {
+        if !self.started {
+            self.started = true;
+            self.hit = self.breakpoint(true);
+            if self.hit.is_some() {
+                self.watch_hit = false;
+                self.moved = true;
+                return;
+            }
+        }
+        self.run_while(|_| true)
+    }
+
+    /// Describes where the program is stopped.
+    pub fn location(&self) -> String {
+        if let Some(msg) = &self.error {
+            return format!("program stopped: {msg}");
+        }
+        if self.interpreter.done() {
+            return format!("program exited, instrCnt: {}", self.interpreter.instr_cnt());
+        }
+        let program = self.interpreter.program();
+        let frame = self.interpreter.env().top_frame();
+        let loc = match program.fetch(frame) {
+            Some(instr) => format!("{}  {instr}", program.loc(frame)),
+            None => format!("{}  <end of function>", program.loc(frame)),
+        };
+        match self.hit {
+            Some(id) => format!("Breakpoint {id}, {loc}"),
+            None => loc,
+        }
+    }
+
+    /// Runs the whole program without interaction, writing the location,
+    /// frames and variables to `out` at every breakpoint hit, and every
+    /// watchpoint hit as it happens.
+    pub fn run_batch(&mut self, out: &mut impl Write) {
+        self.started = true;
+        self.hit = self.breakpoint(true);
+        loop {
+            for line in self.report.drain(..) {
+                writeln!(out, "{line}").expect("write error");
+            }
+            if self.hit.is_some() {
+                let mut lines = vec![self.location()];
+                lines.extend(self.frames());
+                lines.extend(self.locals().iter().map(|line| format!("  {line}")));
+                for line in lines {
+                    writeln!(out, "{line}").expect("write error");
+                }
+            }
+            if !self.running() {
+                break;
+            }
+            self.run_while(|debugger| debugger.report.is_empty());
+        }
+        if let Some(msg) = &self.error {
+            writeln!(out, "program stopped: {msg}").expect("write error");
+        }
+    }
+
+    /// Evaluates `expr` in the frame at `depth` in the stack.
+    pub fn eval(&self, expr: &str, depth: usize) -> Result<Value, String> {
+        let env = self.interpreter.env();
+        let scope = Scope {
+            program: self.interpreter.program(),
+            env,
+            frame: env
+                .stack()
+                .get(depth)
+                .ok_or_else(|| format!("no frame {depth}"))?,
+            icount: self.interpreter.instr_cnt(),
+        };
+        Expr::parse(expr)?.eval(&scope)
+    }
+
+    fn var(&self, name: &str) -> Result<Value, String> {
+        let program = self.interpreter.program();
+        let frame = self.interpreter.env().top_frame();
+        let func = &program.funcs[frame.func];
+        func.var(name)
+            .and_then(|id| frame.get(&id).cloned())
+            .ok_or_else(|| format!("no variable {name} in {}", func.name))
+    }
+
+    fn locals(&self) -> Vec<String> {
+        let program = self.interpreter.program();
+        let frame = self.interpreter.env().top_frame();
+        program
+            .vars(frame)
+            .into_iter()
+            .map(|(name, value)| format!("{name} = {}", show(value)))
+            .collect()
+    }
+
+    fn frames(&self) -> Vec<String> {
+        let program = self.interpreter.program();
+        let stack = self.interpreter.env().stack();
+        stack
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, frame)| format!("#{depth} {}", program.loc(frame)))
+            .collect()
+    }
+
+    fn examine(&self, name: &str, n: usize) -> Result<Vec<String>, String> {
+        let ptr = self.var(name)?;
+        if let Value::ValInt(_) = ptr {
+            return Err(format!("{name} is not a pointer"));
+        }
+        if n > 0 && ptr.peek().is_none() {
+            return Err(format!("{name}+0 is out of bounds"));
+        }
+        Ok((0..n)
+            .map_while(|i| {
+                let offset = 4 * i as i64;
+                let cell = ptr.clone() + Value::new_int(offset);
+                cell.peek()
+                    .map(|int| format!("{name}+{offset}: {}", int as i32))
+            })
+            .collect())
+    }
+
+    /// Attaches `commands` to breakpoint `id`, replacing any it had.
+    pub fn set_commands(&mut self, id: usize, commands: Vec<String>) -> Result<(), String> {
+        self.breakpoints.get_mut(id)?.commands = commands;
+        Ok(())
+    }
+
+    /// Prompt for the next command.
+    fn prompt(&self) -> &'static str {
+        match self.defining {
+            Some(_) => "> ",
+            None => "(misri) ",
+        }
+    }
+
+    /// Runs one debugger command, followed by the commands attached to every
+    /// breakpoint the program stops at because of it. Returns `false` when
+    /// the session should end.
+    ///
+    /// A command that moves the program skips the rest of the list it is in,
+    /// as the list of the next breakpoint takes over.
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> bool {
+        if let Some((_, commands)) = &mut self.defining {
+            if line.trim() != "end" {
+                commands.push(String::from(line.trim()));
+                return true;
+            }
+            let (id, commands) = self.defining.take().unwrap();
+            if let Err(err) = self.set_commands(id, commands) {
+                writeln!(out, "error: {err}").expect("write error");
+            }
+            return true;
+        }
+        self.moved = false;
+        if !self.execute(line, out) {
+            return false;
+        }
+        while self.moved {
+            self.moved = false;
+            let commands = match self.hit.and_then(|id| self.breakpoints.get_mut(id).ok()) {
+                Some(bp) => bp.commands.clone(),
+                None => break,
+            };
+            for command in commands {
+                if !self.execute(&command, out) {
+                    return false;
+                }
+                if self.moved {
+                    break;
+                }
+            }
+        }
+        true
+    }
+
+    fn execute(&mut self, line: &str, out: &mut impl Write) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let arg = rest(line).unwrap_or_default();
+        let lines = match words.as_slice() {
+            ["quit" | "q"] => return false,
+            [] => Ok(Vec::new()),
+            ["break" | "b", _, ..] => self
+                .add_breakpoint(arg)
+                .map(|id| vec![format!("Breakpoint {id} at {arg}")]),
+            ["condition", id, ..] => parse(id)
+                .and_then(|id| self.set_condition(id, rest(arg)))
+                .map(|_| Vec::new()),
+            ["commands", id] => parse(id).and_then(|id| {
+                self.breakpoints.get_mut(id)?;
+                self.defining = Some((id, Vec::new()));
+                Ok(vec![format!(
+                    "Type commands for breakpoint {id}, one per line, ending with \"end\"."
+                )])
+            }),
+            ["commands", id, ..] => parse(id).and_then(|id| {
+                let commands = rest(arg).unwrap_or_default().split(';');
+                let commands = commands.map(str::trim).filter(|cmd| !cmd.is_empty());
+                self.set_commands(id, commands.map(String::from).collect())
+                    .map(|_| Vec::new())
+            }),
+            ["delete" | "d", id] => parse(id)
+                .and_then(|id| self.delete_breakpoint(id))
+                .map(|_| Vec::new()),
+            ["ignore", id, count] => parse(id).and_then(|id| {
+                let count = parse(count)?;
+                self.breakpoints.get_mut(id)?.ignore = count;
+                Ok(vec![format!(
+                    "Will ignore next {count} hits of breakpoint {id}"
+                )])
+            }),
+            ["info", "breakpoints" | "break"] => Ok(self
+                .breakpoints
+                .iter()
+                .flat_map(|bp| {
+                    let commands = bp.commands.iter().map(|cmd| format!("        {cmd}"));
+                    std::iter::once(format!("Breakpoint {bp}")).chain(commands)
+                })
+                .chain(
+                    self.breakpoints
+                        .watches()
+                        .map(|wp| format!("Watchpoint {wp}")),
+                )
+                .collect()),
+            ["record", "on"] => {
+                self.set_record(true);
+                Ok(Vec::new())
+            }
+            ["record", "off"] => {
+                self.set_record(false);
+                Ok(Vec::new())
+            }
+            ["reverse-step" | "rs"] | ["reverse-continue" | "rc"] if self.history.is_none() => {
+                Err(String::from("not recording, start with `record on`"))
+            }
+            ["reverse-step" | "rs"] => match self.reverse_step() {
+                true => Ok(vec![self.location()]),
+                false => Err(String::from("reached the start of the recording")),
+            },
+            ["reverse-continue" | "rc"] => {
+                self.reverse_cont();
+                Ok(vec![self.location()])
+            }
+            [_, ..] if !self.running() => Err(String::from("the program is not running")),
+            ["step" | "s"] => {
+                self.step();
+                Ok(vec![self.location()])
+            }
+            ["next" | "n"] => {
+                self.next();
+                Ok(vec![self.location()])
+            }
+            ["finish"] => {
+                self.finish();
+                Ok(vec![self.location()])
+            }
+            ["continue" | "c"] => {
+                self.cont();
+                Ok(vec![self.location()])
+            }
+            ["print" | "p", _, ..] => self
+                .eval(arg, self.depth() - 1)
+                .map(|value| vec![format!("{arg} = {}", show(&value))]),
+            ["info", "locals"] => Ok(self.locals()),
+            ["info", "frames"] | ["backtrace" | "bt"] => Ok(self.frames()),
+            ["x", name, n] => parse(n).and_then(|n| self.examine(name, n)),
+            ["x", name] => self.examine(name, 1),
+            ["where"] => Ok(vec![self.location()]),
+            ["watch", target] => self
+                .add_watchpoint(target, true)
+                .map(|id| vec![format!("Watchpoint {id} on {target}")]),
+            ["log", target] => self
+                .add_watchpoint(target, false)
+                .map(|id| vec![format!("Watchpoint {id} on {target}, logging only")]),
+            _ => Err(format!("unknown command: {line}")),
+        };
+        let lines = lines.unwrap_or_else(|err| vec![format!("error: {err}")]);
+        for line in self.report.drain(..).chain(lines) {
+            writeln!(out, "{line}").expect("write error");
+        }
+        true
+    }
+
+    /// Reads commands from `input` until it ends or `quit` is given. An empty
+    /// line repeats the previous command.
+    pub fn run(&mut self, input: impl BufRead, out: &mut impl Write) {
+        writeln!(out, "{}", self.location()).expect("write error");
+        let mut last = String::new();
+        let mut lines = input.lines();
+        loop {
+            write!(out, "{}", self.prompt()).expect("write error");
+            out.flush().expect("write error");
+            let line = match lines.next() {
+                Some(line) => line.expect("input error"),
+                None => break,
+            };
+            if self.defining.is_some() {
+                self.command(&line, out);
+                continue;
+            }
+            if !line.trim().is_empty() {
+                last = line;
+            }
+            if !self.command(&last, out) {
+                break;
+            }
+        }
+    }
+
+    /// Runs the commands in `script` without interaction, echoing each one
+    /// after its prompt so that the output reads like a session. Blank lines
+    /// and lines starting with `#` are skipped.
+    pub fn run_script(&mut self, script: impl BufRead, out: &mut impl Write) {
+        writeln!(out, "{}", self.location()).expect("write error");
+        for line in script.lines() {
+            let line = line.expect("input error");
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            writeln!(out, "{}{line}", self.prompt()).expect("write error");
+            if !self.command(line, out) {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_catch() {
+        let inner = catch(|| catch(|| panic!("inner")));
+        assert_eq!(inner, Ok(Err(String::from("inner"))));
+        assert!(!CATCHING.get());
+    }
+
+    fn session(code: &str, input: &str, commands: &str) -> String {
+        let program = Parser::with_file("a.ir", code).parse();
+        let interpreter = Interpreter::new(program, input.as_bytes(), Vec::new());
+        let mut debugger = Debugger::new(interpreter);
+        let out = &mut Vec::new();
+        for line in commands.lines() {
+            debugger.command(line.trim(), out);
+        }
+        String::from_utf8(out.clone()).unwrap()
+    }
+
+    const FIB: &str = "FUNCTION fib :
+PARAM n
+IF n > #1 GOTO rec
+RETURN n
+LABEL rec :
+t1 := n - #1
+r1 := CALL fib(t1)
+t2 := n - #2
+r2 := CALL fib(t2)
+u := r1 + r2
+RETURN u
+
+FUNCTION main :
+DEC arr 8
+*arr := #114
+x := CALL fib(#3)
+RETURN x
+";
+
+    #[test]
+    fn test_step() {
+        let out = session(
+            FIB,
+            "",
+            "step
+             step
+             x arr 3
+             n
+             step
+             step
+             step
+             info frames
+             print n
+             finish
+             info locals
+             continue
+             step",
+        );
+        assert_eq!(
+            out,
+            "main (a.ir:15)  *arr := #114
+main (a.ir:16)  ARG #3
+arr+0: 114
+arr+4: 0
+main (a.ir:16)  x := CALL fib
+fib (a.ir:2)  PARAM n
+fib (a.ir:3)  IF n > #1 GOTO rec
+fib (a.ir:5)  LABEL rec :
+#0 fib (a.ir:5)
+#1 main (a.ir:16)
+n = 3
+main (a.ir:17)  RETURN x
+arr = ptr+0 (8 bytes)
+x = 2
+program exited, instrCnt: 35
+error: the program is not running
+"
+        );
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let out = session(
+            FIB,
+            "",
+            "break fib
+             break fib:rec
+             ignore 1 2
+             break 9
+             continue
+             info frames
+             print n
+             delete 2
+             continue
+             continue
+             info breakpoints
+             break nope",
+        );
+        assert_eq!(
+            out,
+            "Breakpoint 1 at fib
+Breakpoint 2 at fib:rec
+Will ignore next 2 hits of breakpoint 1
+Breakpoint 3 at 9
+Breakpoint 2, fib (a.ir:5)  LABEL rec :
+#0 fib (a.ir:5)
+#1 main (a.ir:16)
+n = 3
+Breakpoint 1, fib (a.ir:2)  PARAM n
+Breakpoint 3, fib (a.ir:9)  ARG t2
+Breakpoint 1 at fib, hit 3 times
+Breakpoint 3 at 9, hit 1 times
+error: no instruction at nope
+"
+        );
+    }
+
+    #[test]
+    fn test_breakpoint_at_entry() {
+        let out = session(
+            FIB,
+            "",
+            "break main
+             continue
+             continue",
+        );
+        assert_eq!(
+            out,
+            "Breakpoint 1 at main
+Breakpoint 1, main (a.ir:14)  DEC arr 8
+program exited, instrCnt: 35
+"
+        );
+    }
+
+    #[test]
+    fn test_condition() {
+        let out = session(
+            FIB,
+            "",
+            "break 3 if n == 1 && depth > 2
+             break fib:rec if nope
+             continue
+             print depth * 10 + n
+             print icount
+             delete 2
+             continue
+             condition 1 n == 0
+             continue
+             condition 1
+             continue
+             print n
+             info breakpoints
+             break fib if n =",
+        );
+        assert_eq!(
+            out,
+            "Breakpoint 1 at 3 if n == 1 && depth > 2
+Breakpoint 2 at fib:rec if nope
+error in condition of breakpoint 2: no variable nope in fib
+Breakpoint 2, fib (a.ir:5)  LABEL rec :
+depth * 10 + n = 23
+icount = 6
+Breakpoint 1, fib (a.ir:3)  IF n > #1 GOTO rec
+Breakpoint 1, fib (a.ir:3)  IF n > #1 GOTO rec
+Breakpoint 1, fib (a.ir:3)  IF n > #1 GOTO rec
+n = 1
+Breakpoint 1 at 3, hit 3 times
+error: unexpected character '='
+"
+        );
+    }
+
+    #[test]
+    fn test_script() {
+        let program = Parser::with_file("a.ir", FIB).parse();
+        let interpreter = Interpreter::new(program, "".as_bytes(), Vec::new());
+        let mut debugger = Debugger::new(interpreter);
+        let out = &mut Vec::new();
+        let script = "# every call of fib that recurses
+            break fib:rec
+            commands 1
+              print n
+              continue
+              print never
+            end
+            break 10
+            commands 2 print depth; print r1 + r2
+
+            continue
+            info breakpoints
+            quit
+            print n";
+        debugger.run_script(script.as_bytes(), out);
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "main (a.ir:14)  DEC arr 8
+(misri) break fib:rec
+Breakpoint 1 at fib:rec
+(misri) commands 1
+Type commands for breakpoint 1, one per line, ending with \"end\".
+> print n
+> continue
+> print never
+> end
+(misri) break 10
+Breakpoint 2 at 10
+(misri) commands 2 print depth; print r1 + r2
+(misri) continue
+Breakpoint 1, fib (a.ir:5)  LABEL rec :
+n = 3
+Breakpoint 1, fib (a.ir:5)  LABEL rec :
+n = 2
+Breakpoint 2, fib (a.ir:10)  u := r1 + r2
+depth = 3
+r1 + r2 = 1
+(misri) info breakpoints
+Breakpoint 1 at fib:rec, hit 2 times
+        print n
+        continue
+        print never
+Breakpoint 2 at 10, hit 1 times
+        print depth
+        print r1 + r2
+(misri) quit
+"
+        );
+    }
+
+    #[test]
+    fn test_batch() {
+        let program = Parser::with_file("a.ir", FIB).parse();
+        let interpreter = Interpreter::new(program, "".as_bytes(), Vec::new());
+        let mut debugger = Debugger::new(interpreter);
+        debugger.add_breakpoint("main").unwrap();
+        debugger.add_breakpoint("a.ir:17").unwrap();
+        let out = &mut Vec::new();
+        debugger.run_batch(out);
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "Breakpoint 1, main (a.ir:14)  DEC arr 8
+#0 main (a.ir:14)
+  arr = 0
+  x = 0
+Breakpoint 2, main (a.ir:17)  RETURN x
+#0 main (a.ir:17)
+  arr = ptr+0 (8 bytes)
+  x = 2
+"
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let out = session(
+            FIB,
+            "",
+            "step
+             watch *arr
+             log fib:n
+             watch x
+             watch nope
+             continue
+             continue
+             delete 2
+             continue
+             info breakpoints
+             continue",
+        );
+        assert_eq!(
+            out,
+            "main (a.ir:15)  *arr := #114
+Watchpoint 1 on *arr
+Watchpoint 2 on fib:n, logging only
+Watchpoint 3 on x
+error: no variable nope in main
+Watchpoint 1, *arr: 0 -> 114 by main (a.ir:15)  *arr := #114
+main (a.ir:16)  ARG #3
+Watchpoint 2, fib:n: 0 -> 3 by fib (a.ir:2)  PARAM n
+Watchpoint 2, fib:n: 0 -> 2 by fib (a.ir:2)  PARAM n
+Watchpoint 2, fib:n: 0 -> 1 by fib (a.ir:2)  PARAM n
+Watchpoint 2, fib:n: 0 -> 1 by fib (a.ir:2)  PARAM n
+Watchpoint 3, x: 0 -> 2 by fib (a.ir:11)  RETURN u
+main (a.ir:17)  RETURN x
+program exited, instrCnt: 35
+Watchpoint 1 on *arr, hit 1 times
+Watchpoint 3 on x, hit 1 times
+error: the program is not running
+"
+        );
+    }
+
+    #[test]
+    fn test_watch_pointer_store() {
+        let out = session(
+            "FUNCTION main :
+             DEC q 8
+             *q := #5
+             *q := q
+             x := *q
+             WRITE x
+             RETURN #0",
+            "",
+            "step
+             watch *q
+             continue
+             continue",
+        );
+        assert_eq!(
+            out,
+            "main (a.ir:3)  *q := #5
+Watchpoint 1 on *q
+Watchpoint 1, *q: 0 -> 5 by main (a.ir:3)  *q := #5
+main (a.ir:4)  *q := q
+program exited, instrCnt: 5
+"
+        );
+    }
+
+    #[test]
+    fn test_reverse() {
+        let out = session(
+            "FUNCTION main :
+             READ n
+             DEC arr 8
+             *arr := n
+             i := #0
+             LABEL loop :
+             i := i + #1
+             IF i < #3 GOTO loop
+             *arr := #0
+             WRITE n
+             RETURN #0",
+            "7\n",
+            "reverse-step
+             record on
+             break 6
+             step
+             step
+             watch *arr
+             continue
+             continue
+             continue
+             continue
+             continue
+             rs
+             rs
+             print i
+             rc
+             rc
+             x arr 1
+             rc
+             rc
+             rs
+             continue",
+        );
+        assert_eq!(
+            out,
+            "error: not recording, start with `record on`
+Breakpoint 1 at 6
+main (a.ir:3)  DEC arr 8
+main (a.ir:4)  *arr := n
+Watchpoint 2 on *arr
+Watchpoint 2, *arr: 0 -> 7 by main (a.ir:4)  *arr := n
+main (a.ir:5)  i := #0
+Breakpoint 1, main (a.ir:6)  LABEL loop :
+Breakpoint 1, main (a.ir:6)  LABEL loop :
+Breakpoint 1, main (a.ir:6)  LABEL loop :
+Watchpoint 2, *arr: 7 -> 0 by main (a.ir:9)  *arr := #0
+main (a.ir:10)  WRITE n
+main (a.ir:9)  *arr := #0
+main (a.ir:8)  IF i < #3 GOTO loop
+i = 3
+Breakpoint 1, main (a.ir:6)  LABEL loop :
+Breakpoint 1, main (a.ir:6)  LABEL loop :
+arr+0: 7
+Breakpoint 1, main (a.ir:6)  LABEL loop :
+Watchpoint 2, *arr is changed by
+main (a.ir:4)  *arr := n
+main (a.ir:3)  DEC arr 8
+Breakpoint 1, main (a.ir:6)  LABEL loop :
+"
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let program = Parser::with_file(
+            "a.ir",
+            "FUNCTION main :
+             i := #1
+             i := #2
+             i := #3
+             RETURN #0",
+        )
+        .parse();
+        let interpreter = Interpreter::new(program, "".as_bytes(), Vec::new());
+        let mut debugger = Debugger::new(interpreter);
+        debugger.history_limit = 2;
+        let out = &mut Vec::new();
+        for line in [
+            "record on",
+            "step",
+            "step",
+            "record on",
+            "step",
+            "rs",
+            "rs",
+            "rs",
+            "p i",
+        ] {
+            debugger.command(line, out);
+        }
+        assert_eq!(
+            String::from_utf8_lossy(out),
+            "main (a.ir:3)  i := #2
+main (a.ir:4)  i := #3
+main (a.ir:5)  RETURN #0
+main (a.ir:4)  i := #3
+main (a.ir:3)  i := #2
+error: reached the start of the recording
+i = 1
+"
+        );
+    }
+
+    #[test]
+    fn test_examine() {
+        let out = session(
+            "FUNCTION main :
+             DEC a 8
+             p := a - #4
+             RETURN #0",
+            "",
+            "step
+             step
+             x p 2
+             x a 3",
+        );
+        assert_eq!(
+            out,
+            "main (a.ir:3)  p := a - #4
+main (a.ir:4)  RETURN #0
+error: p+0 is out of bounds
+a+0: 0
+a+4: 0
+"
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        let out = session(
+            "FUNCTION main :
+             ASSERT #1 == #2
+             RETURN #0",
+            "",
+            "continue
+             print x",
+        );
+        assert_eq!(
+            out,
+            "program stopped: assertion #1 == #2 failed with 1 == 2 at main (a.ir:2)
+error: the program is not running
+"
+        );
+    }
+}
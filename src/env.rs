@@ -3,9 +3,11 @@ use crate::{
     value::Value,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Frame {
     map: Vec<Value>,
+    /// Arguments passed by the caller that are yet to be taken by `PARAM`.
+    args: Vec<Value>,
     pub func: usize,
     pub pc: usize,
 }
@@ -14,6 +16,7 @@ impl Frame {
     pub fn new(func: &Func) -> Frame {
         Frame {
             map: vec![Value::default(); func.nreg + 1],
+            args: Vec::new(),
             pc: 0,
             func: func.id,
         }
@@ -26,12 +29,50 @@ impl Frame {
     pub fn set(&mut self, id: &usize, value: &Value) {
         self.map[*id] = value.clone();
     }
+
+    /// Makes room for `nreg` variables, for a function that gained some after
+    /// the frame was pushed.
+    pub fn grow(&mut self, nreg: usize) {
+        if self.map.len() < nreg + 1 {
+            self.map.resize(nreg + 1, Value::default())
+        }
+    }
+}
+
+/// A change made to the state, recorded when enabled with `Env::record`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// Variable `id` of the frame at `depth` in the stack was set.
+    Set {
+        depth: usize,
+        id: usize,
+        old: Value,
+        new: Value,
+    },
+    /// The cell `ptr` points to was stored to.
+    Store { ptr: Value, old: i64, new: i64 },
+    /// A frame was pushed, taking these pending arguments.
+    Push { args: Vec<Value> },
+    /// This frame was popped.
+    Pop { frame: Frame },
+    /// An argument was pushed by `ARG`.
+    Arg,
+    /// This argument was taken by `PARAM`.
+    Param(Value),
+    /// These pending arguments were taken by an intrinsic.
+    Take(Vec<Value>),
+    /// The pc of the top frame moved away from `old`.
+    Pc { old: usize },
+    /// This line of input was consumed by `READ`.
+    Read(String),
 }
 
 #[derive(Debug, Clone)]
 pub struct Env {
     stack: Vec<Frame>,
+    /// Arguments pushed by `ARG` for the next call.
     args: Vec<Value>,
+    effects: Option<Vec<Effect>>,
 }
 
 impl Env {
@@ -39,6 +80,52 @@ impl Env {
         Env {
             stack: vec![Frame::new(&program.funcs[program.entry])],
             args: Vec::new(),
+            effects: None,
+        }
+    }
+
+    /// Starts or stops recording the effects of everything that changes the
+    /// state.
+    pub fn record(&mut self, on: bool) {
+        self.effects = on.then(Vec::new)
+    }
+
+    /// Takes the effects recorded so far.
+    pub fn effects(&mut self) -> Vec<Effect> {
+        self.effects
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Records `effect` if recording is on.
+    pub fn log(&mut self, effect: impl FnOnce(&Env) -> Effect) {
+        if let Some(mut effects) = self.effects.take() {
+            effects.push(effect(self));
+            self.effects = Some(effects)
+        }
+    }
+
+    /// Reverts `effect`, which must be the latest effect not yet reverted.
+    /// `Read` is left to the caller, which owns the input.
+    pub fn undo(&mut self, effect: Effect) {
+        match effect {
+            Effect::Set { depth, id, old, .. } => self.stack[depth].set(&id, &old),
+            Effect::Store { ptr, old, .. } => {
+                ptr.store(Value::new_int(old));
+            }
+            Effect::Push { args } => {
+                self.stack.pop();
+                self.args = args
+            }
+            Effect::Pop { frame } => self.stack.push(frame),
+            Effect::Arg => {
+                self.args.pop();
+            }
+            Effect::Param(value) => self.top_frame_mut().args.push(value),
+            Effect::Take(args) => self.args = args,
+            Effect::Pc { old } => self.top_frame_mut().pc = old,
+            Effect::Read(_) => (),
         }
     }
 
@@ -46,6 +133,11 @@ impl Env {
         self.stack.last_mut().unwrap()
     }
 
+    /// Frames from the outermost call to the innermost.
+    pub fn stack(&self) -> &[Frame] {
+        &self.stack
+    }
+
     pub fn top_frame(&self) -> &Frame {
         self.stack.last().unwrap()
     }
@@ -55,6 +147,7 @@ impl Env {
     }
 
     pub fn pc_set(&mut self, pc: usize) {
+        self.log(|env| Effect::Pc { old: env.pc() });
         self.top_frame_mut().pc = pc
     }
 
@@ -75,24 +168,63 @@ impl Env {
 
     pub fn set(&mut self, operand: Operand, value: Value) {
         if let Operand::Reg { id, .. } = operand {
+            self.log(|env| Effect::Set {
+                depth: env.stack.len() - 1,
+                id,
+                old: env.top_frame().get(&id).cloned().unwrap_or_default(),
+                new: value.clone(),
+            });
             self.top_frame_mut().set(&id, &value)
         }
     }
 
+    pub fn store(&mut self, ptr: &Value, value: Value) {
+        let old = ptr.store(value);
+        // Storing a pointer leaves the cell as it was.
+        let new = ptr.peek().unwrap_or(old);
+        self.log(|_| Effect::Store {
+            ptr: ptr.clone(),
+            old,
+            new,
+        })
+    }
+
     pub fn push_arg(&mut self, value: Value) {
+        self.log(|_| Effect::Arg);
         self.args.push(value)
     }
 
     pub fn pop_arg(&mut self) -> Value {
-        self.args.pop().expect("arg stack empty")
+        let value = self.top_frame_mut().args.pop().expect("arg stack empty");
+        self.log(|_| Effect::Param(value.clone()));
+        value
     }
 
+    /// Takes the arguments pushed since the last call, in `PARAM` order.
+    pub fn take_args(&mut self) -> Vec<Value> {
+        let mut args = std::mem::take(&mut self.args);
+        self.log(|_| Effect::Take(args.clone()));
+        args.reverse();
+        args
+    }
+
+    pub fn nargs(&self) -> usize {
+        self.args.len()
+    }
+
+    /// Pushes a frame for `func`, which owns the arguments pushed so far.
     pub fn push_frame(&mut self, func: &Func) {
-        self.stack.push(Frame::new(func))
+        let mut frame = Frame::new(func);
+        frame.args = std::mem::take(&mut self.args);
+        self.log(|_| Effect::Push {
+            args: frame.args.clone(),
+        });
+        self.stack.push(frame)
     }
 
     pub fn pop_frame(&mut self) {
-        self.stack.pop();
+        let frame = self.stack.pop().unwrap();
+        self.log(|_| Effect::Pop { frame });
     }
 }
 
@@ -106,11 +238,10 @@ mod tests {
     fn test_get_set() {
         let mut env = Env::new(&Program {
             funcs: VecDeque::from([Func {
-                name: String::from("foo"),
-                body: Vec::new(),
                 nreg: 2,
-                id: 0,
+                ..Func::new("foo")
             }]),
+            files: Vec::new(),
             entry: 0,
         });
 
@@ -121,10 +252,8 @@ mod tests {
         assert_eq!(env.get(&Operand::from(("p", 1))), Value::new_ptr(514));
 
         env.push_frame(&Func {
-            name: String::new(),
-            body: Vec::new(),
             nreg: 2,
-            id: 0,
+            ..Func::new("")
         });
         env.set(Operand::from(("x", 0)), Value::new_int(1919));
         assert_eq!(env.get(&Operand::from(("x", 0))), Value::new_int(1919));
@@ -133,4 +262,62 @@ mod tests {
         assert_eq!(env.get(&Operand::from(("x", 0))), Value::new_int(514));
         assert_eq!(env.get(&Operand::from(("p", 1))), Value::new_ptr(514));
     }
+
+    #[test]
+    fn test_effects() {
+        let mut env = Env::new(&Program {
+            funcs: VecDeque::from([Func {
+                nreg: 2,
+                ..Func::new("foo")
+            }]),
+            files: Vec::new(),
+            entry: 0,
+        });
+
+        env.set(Operand::from(("x", 0)), Value::new_int(1));
+        env.record(true);
+        env.set(Operand::from(("x", 0)), Value::new_int(2));
+        let ptr = Value::new_ptr(8) + Value::new_int(4);
+        env.store(&ptr, Value::new_int(3));
+        assert_eq!(
+            env.effects(),
+            vec![
+                Effect::Set {
+                    depth: 0,
+                    id: 0,
+                    old: Value::new_int(1),
+                    new: Value::new_int(2)
+                },
+                Effect::Store {
+                    ptr,
+                    old: 0,
+                    new: 3
+                }
+            ]
+        );
+        assert_eq!(env.effects(), vec![]);
+    }
+
+    #[test]
+    fn test_args() {
+        let func = Func {
+            nreg: 2,
+            ..Func::new("foo")
+        };
+        let mut env = Env::new(&Program {
+            funcs: VecDeque::from([func.clone()]),
+            files: Vec::new(),
+            entry: 0,
+        });
+
+        env.push_arg(Value::new_int(2));
+        env.push_arg(Value::new_int(1));
+        assert_eq!(env.nargs(), 2);
+        env.push_frame(&func);
+        assert_eq!(env.nargs(), 0);
+        env.push_arg(Value::new_int(3));
+        assert_eq!(env.pop_arg(), Value::new_int(1));
+        assert_eq!(env.take_args(), vec![Value::new_int(3)]);
+        assert_eq!(env.pop_arg(), Value::new_int(2));
+    }
 }
@@ -4,11 +4,35 @@ use std::{
 };
 
 use crate::{
-    env::Env,
-    instr::{ArithOp, Instr::*, Program, RelOp},
+    env::{Effect, Env},
+    instr::{ArithOp, Instr::*, Program},
     value::Value,
 };
 
+/// What happens when a function runs off the end of its body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImplicitReturn {
+    /// Stop with an error.
+    Error,
+    /// Return as if by `RETURN #0`.
+    Zero,
+}
+
+/// Observes execution one instruction at a time, when running with
+/// `Interpreter::exec_with`.
+pub trait Hook {
+    /// Called before each instruction, with the state it runs in.
+    fn before(&mut self, _program: &Program, _env: &Env) {}
+
+    /// Called after each instruction, with the state it leaves.
+    fn after(&mut self, _program: &Program, _env: &Env) {}
+
+    /// Called instead of `after` for the instruction that stopped the
+    /// program, which is not counted as executed. The stack still holds the
+    /// frame it stopped in.
+    fn stop(&mut self, _program: &Program, _env: &Env) {}
+}
+
 pub struct Interpreter<T, U>
 where
     U: std::io::Write,
@@ -17,6 +41,13 @@ where
     env: Env,
     fin: BufReader<T>,
     fout: BufWriter<U>,
+    implicit_return: ImplicitReturn,
+    instr_cnt: usize,
+    done: bool,
+    /// Status given to `exit`, if the program called it.
+    exit_code: Option<i32>,
+    /// Input lines given back by `undo`, to be read again.
+    unread: Vec<String>,
 }
 
 impl<T, U> Interpreter<T, U>
@@ -36,22 +67,130 @@ where
             env,
             fin: BufReader::new(fin),
             fout: BufWriter::new(fout),
+            implicit_return: ImplicitReturn::Error,
+            instr_cnt: 0,
+            done: false,
+            exit_code: None,
+            unread: Vec::new(),
+        }
+    }
+
+    pub fn set_implicit_return(&mut self, implicit_return: ImplicitReturn) {
+        self.implicit_return = implicit_return
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// The program under execution, for a REPL that grows it. Frames on the
+    /// stack must stay valid for the changed program.
+    pub fn program_mut(&mut self) -> &mut Program {
+        &mut self.program
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    pub fn instr_cnt(&self) -> usize {
+        self.instr_cnt
+    }
+
+    /// Whether the program has stopped, after which `tick` does nothing.
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    /// Status the program exits with: what it gave to `exit`, or else 0.
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code.unwrap_or_default()
+    }
+
+    /// Flushes and gives back what the program wrote.
+    pub fn into_output(self) -> U {
+        self.fout.into_inner().expect("write error")
+    }
+
+    pub fn flush(&mut self) {
+        self.fout.flush().expect("write error")
+    }
+
+    /// Reverts the effects recorded while executing one instruction, moving
+    /// back to before that instruction.
+    pub fn undo(&mut self, effects: Vec<Effect>) {
+        for effect in effects.into_iter().rev() {
+            match effect {
+                Effect::Read(line) => self.unread.push(line),
+                Effect::Pc { .. } => {
+                    self.instr_cnt -= 1;
+                    self.env.undo(effect)
+                }
+                effect => self.env.undo(effect),
+            }
         }
+        self.done = false;
+        self.exit_code = None
     }
 
     pub fn exec(&mut self) -> usize {
-        let mut instr_cnt = 0;
-        while let Some(next_pc) = self.step() {
-            self.env.pc_set(next_pc);
-            instr_cnt += 1
+        while self.tick() {}
+        self.instr_cnt
+    }
+
+    /// Like `exec`, but tells every hook about each instruction.
+    pub fn exec_with(&mut self, hooks: &mut [&mut dyn Hook]) -> usize {
+        while !self.done {
+            for hook in hooks.iter_mut() {
+                hook.before(&self.program, &self.env)
+            }
+            let counted = self.tick();
+            for hook in hooks.iter_mut() {
+                match counted {
+                    true => hook.after(&self.program, &self.env),
+                    false => hook.stop(&self.program, &self.env),
+                }
+            }
+        }
+        self.instr_cnt
+    }
+
+    /// Executes one instruction and moves to the next, returning `false` once
+    /// the program has stopped.
+    pub fn tick(&mut self) -> bool {
+        if self.done {
+            return false;
+        }
+        match self.step() {
+            Some(next_pc) => {
+                self.env.pc_set(next_pc);
+                self.instr_cnt += 1;
+                true
+            }
+            None => {
+                self.done = true;
+                false
+            }
         }
-        instr_cnt
     }
 
     pub fn step(&mut self) -> Option<usize> {
         let program = &self.program;
         let env = &mut self.env;
-        let instr = program.fetch(env.top_frame());
+        let instr = match program.fetch(env.top_frame()) {
+            Some(instr) => instr,
+            None => match self.implicit_return {
+                ImplicitReturn::Zero => Return(None),
+                ImplicitReturn::Error => panic!(
+                    "{} ends without RETURN",
+                    program.funcs[env.top_frame().func].name
+                ),
+            },
+        };
         match instr {
             Arith(x, y, op, z) => {
                 let vy = env.get(&y);
@@ -76,7 +215,7 @@ where
             Store(x, y) => {
                 let val = env.get(&y);
                 let addr = env.get(&x);
-                addr.store(val);
+                env.store(&addr, val);
                 Some(env.pc_next())
             }
             Load(x, y) => {
@@ -94,8 +233,11 @@ where
             }
             Label(_) => Some(env.pc_next()),
             Read(x) => {
-                let buf = &mut String::new();
-                self.fin.read_line(buf).expect("input error");
+                let buf = &mut self.unread.pop().unwrap_or_default();
+                if buf.is_empty() {
+                    self.fin.read_line(buf).expect("input error");
+                }
+                env.log(|_| Effect::Read(buf.clone()));
                 let int: i64 = buf.trim().parse().expect("input error");
                 env.set(x, Value::new_int(int));
                 Some(env.pc_next())
@@ -109,51 +251,93 @@ where
                 env.set(x, Value::new_ptr(size as usize));
                 Some(env.pc_next())
             }
-            Call { id, .. } => {
-                env.push_frame(&program.funcs[id]);
-                Some(env.pc())
+            Call { x, id, .. } => {
+                let func = &program.funcs[id];
+                if env.nargs() != func.arity() {
+                    panic!(
+                        "{} passes {} arguments to {}, which takes {}, at {}",
+                        program.funcs[env.top_frame().func].name,
+                        env.nargs(),
+                        func.name,
+                        func.arity(),
+                        program.loc(env.top_frame())
+                    )
+                }
+                match func.builtin {
+                    Some(builtin) => {
+                        let args = env.take_args();
+                        let loc = || program.loc(env.top_frame());
+                        let value = match builtin.call(args, &mut self.fout, loc) {
+                            Ok(value) => value,
+                            Err(code) => {
+                                self.exit_code = Some(code);
+                                return None;
+                            }
+                        };
+                        if let Some(x) = x {
+                            env.set(x, value)
+                        }
+                        Some(env.pc_next())
+                    }
+                    None => {
+                        env.push_frame(func);
+                        Some(env.pc())
+                    }
+                }
             }
             Return(x) => {
                 if env.top_frame().func == program.entry {
                     return None;
                 }
-                let value = env.get(&x);
+                let value = x.map_or(Value::default(), |x| env.get(&x));
                 env.pop_frame();
                 let func = &program.funcs[env.top_frame().func];
                 match &func.body[env.pc()] {
-                    Call { x, .. } => env.set(x.clone(), value),
+                    Call { x: Some(x), .. } => env.set(x.clone(), value),
+                    Call { x: None, .. } => (),
                     _ => panic!("return error"),
                 };
                 Some(env.pc_next())
             }
             Goto { id, .. } => Some(id),
             Cond { x, op, y, id, .. } => {
-                let vx = env.get(&x);
-                let vy = env.get(&y);
-                let jmp = match op {
-                    RelOp::LT => vx < vy,
-                    RelOp::LE => vx <= vy,
-                    RelOp::GT => vx > vy,
-                    RelOp::GE => vx >= vy,
-                    RelOp::EQ => vx == vy,
-                    RelOp::NE => vx != vy,
-                };
-                if jmp {
+                if op.eval(&env.get(&x), &env.get(&y)) {
                     Some(id)
                 } else {
                     Some(env.pc_next())
                 }
             }
+            Assert { x, op, y, msg } => {
+                let vx = env.get(&x);
+                let vy = env.get(&y);
+                if !op.eval(&vx, &vy) {
+                    let loc = program.loc(env.top_frame());
+                    let msg = msg.map_or(String::new(), |msg| format!(": {msg}"));
+                    panic!("assertion {x} {op} {y} failed with {vx} {op} {vy} at {loc}{msg}")
+                }
+                Some(env.pc_next())
+            }
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::parser::Parser;
 
     use super::*;
 
+    /// Runs `code`, parsed as `a.ir`, with the hook `new` builds from the
+    /// linked program. Returns the program, the hook and the instruction
+    /// count.
+    pub fn run_with<H: Hook>(code: &str, new: impl FnOnce(&Program) -> H) -> (Program, H, usize) {
+        let program = Parser::with_file("a.ir", code).parse();
+        let mut interpreter = Interpreter::new(program, "".as_bytes(), Vec::new());
+        let mut hook = new(interpreter.program());
+        let instr_cnt = interpreter.exec_with(&mut [&mut hook]);
+        (interpreter.program().clone(), hook, instr_cnt)
+    }
+
     fn config(code: &str, input: &str, output: &str) {
         let mut parser = Parser::from(code);
         let program = parser.parse();
@@ -257,4 +441,148 @@ mod tests {
             514\n",
         );
     }
+
+    #[test]
+    fn test_intrinsic() {
+        config(
+            "FUNCTION max :
+             PARAM a
+             PARAM b
+             RETURN #42
+
+             FUNCTION main :
+             ARG #-5
+             x := CALL abs
+             WRITE x
+             ARG #7
+             ARG #3
+             y := CALL min
+             WRITE y
+             ARG #7
+             ARG #3
+             z := CALL max
+             WRITE z
+             ARG #104
+             u := CALL putchar
+             ARG #105
+             u := CALL putchar
+             ARG #10
+             u := CALL putchar
+             ARG #0
+             u := CALL exit
+             WRITE x
+             RETURN #0
+            ",
+            "",
+            "5\n3\n42\nhi\n",
+        );
+    }
+
+    #[test]
+    fn test_exit() {
+        let program = Parser::from(
+            "FUNCTION main :
+             WRITE #1
+             ARG #3
+             u := CALL exit
+             WRITE #2
+             RETURN #0
+            ",
+        )
+        .parse();
+        let mut interpreter = Interpreter::new(program, "".as_bytes(), Vec::new());
+        assert_eq!(interpreter.exit_code(), 0);
+        assert_eq!(interpreter.exec(), 2);
+        assert_eq!(interpreter.exit_code(), 3);
+        assert_eq!(interpreter.into_output(), b"1\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "failed with 3 > 4 at main (<input>:4): too small")]
+    fn test_assert() {
+        config(
+            "FUNCTION main :
+             x := #3
+             ASSERT x == #3
+             ASSERT x > #4 \"too small\"
+             RETURN #0
+            ",
+            "",
+            "",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "assertion failed with assert(0) at main (<input>:3)")]
+    fn test_assert_intrinsic() {
+        config(
+            "FUNCTION main :
+             ARG #0
+             CALL assert
+             RETURN #0
+            ",
+            "",
+            "",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "main passes 2 arguments to id, which takes 1")]
+    fn test_arg_mismatch() {
+        config(
+            "FUNCTION id :
+             PARAM n
+             RETURN n
+
+             FUNCTION main :
+             ARG #1
+             LABEL l :
+             ARG #2
+             x := CALL id
+             RETURN #0
+            ",
+            "",
+            "",
+        );
+    }
+
+    #[test]
+    fn test_void() {
+        let code = "FUNCTION hello :
+             WRITE #1
+             RETURN
+
+             FUNCTION bye :
+             WRITE #2
+
+             FUNCTION main :
+             CALL hello
+             x := CALL hello
+             WRITE x
+             x := CALL bye
+             WRITE x
+             RETURN #0
+            ";
+        let mut interpreter =
+            Interpreter::new(Parser::from(code).parse(), "".as_bytes(), Vec::new());
+        interpreter.set_implicit_return(ImplicitReturn::Zero);
+        interpreter.exec();
+        assert_eq!(interpreter.fout.into_inner().unwrap(), b"1\n1\n0\n2\n0\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "bye ends without RETURN")]
+    fn test_implicit_return() {
+        config(
+            "FUNCTION bye :
+             WRITE #2
+
+             FUNCTION main :
+             CALL bye
+             RETURN #0
+            ",
+            "",
+            "",
+        );
+    }
 }
@@ -0,0 +1,281 @@
+use std::fmt::Display;
+
+use crate::{
+    debug::catch,
+    env::{Env, Frame},
+    instr::{ArithOp, Program, RelOp},
+    lexer::{Lexer, Token},
+    value::Value,
+};
+
+/// An expression over the state of a stopped program, as used in breakpoint
+/// conditions and `print`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Int(i64),
+    /// A variable of the frame, or `depth` and `icount` if there is none of
+    /// that name.
+    Var(String),
+    /// `*e`, the cell `e` points to.
+    Deref(Box<Expr>),
+    Neg(Box<Expr>),
+    Arith(Box<Expr>, ArithOp, Box<Expr>),
+    Rel(Box<Expr>, RelOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// What an expression is evaluated against.
+pub struct Scope<'a> {
+    pub program: &'a Program,
+    pub env: &'a Env,
+    pub frame: &'a Frame,
+    pub icount: usize,
+}
+
+struct ExprParser {
+    lexer: Lexer,
+}
+
+impl ExprParser {
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.lexer.consume().0 {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected {expected:?}, found {token:?}")),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.parse_and()?;
+        while self.lexer.peek().0 == Token::TokOr {
+            self.lexer.consume();
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.parse_rel()?;
+        while self.lexer.peek().0 == Token::TokAnd {
+            self.lexer.consume();
+            lhs = Expr::And(Box::new(lhs), Box::new(self.parse_rel()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_rel(&mut self) -> Result<Expr, String> {
+        let lhs = self.parse_sum()?;
+        let op = match self.lexer.peek().0 {
+            Token::TokLT => RelOp::LT,
+            Token::TokLE => RelOp::LE,
+            Token::TokGT => RelOp::GT,
+            Token::TokGE => RelOp::GE,
+            Token::TokEQ => RelOp::EQ,
+            Token::TokNE => RelOp::NE,
+            _ => return Ok(lhs),
+        };
+        self.lexer.consume();
+        Ok(Expr::Rel(Box::new(lhs), op, Box::new(self.parse_sum()?)))
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.parse_term()?;
+        loop {
+            let op = match self.lexer.peek().0 {
+                Token::TokAdd => ArithOp::Add,
+                Token::TokSub => ArithOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.lexer.consume();
+            lhs = Expr::Arith(Box::new(lhs), op, Box::new(self.parse_term()?));
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = match self.lexer.peek().0 {
+                Token::TokStar => ArithOp::Mul,
+                Token::TokDiv => ArithOp::Div,
+                _ => return Ok(lhs),
+            };
+            self.lexer.consume();
+            lhs = Expr::Arith(Box::new(lhs), op, Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.lexer.consume().0 {
+            Token::TokSub => Ok(Expr::Neg(Box::new(self.parse_unary()?))),
+            Token::TokStar => Ok(Expr::Deref(Box::new(self.parse_unary()?))),
+            Token::TokLParen => {
+                let expr = self.parse_or()?;
+                self.expect(Token::TokRParen)?;
+                Ok(expr)
+            }
+            Token::TokSharp => match self.lexer.consume().0 {
+                Token::TokInt(int) => Ok(Expr::Int(int)),
+                token => Err(format!("expected an integer, found {token:?}")),
+            },
+            Token::TokInt(int) => Ok(Expr::Int(int)),
+            Token::TokIden(name) => Ok(Expr::Var(name)),
+            token => Err(format!("unexpected {token:?}")),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Operands that are themselves binary are parenthesized.
+        let operand = |expr: &Expr| match expr {
+            Expr::Arith(..) | Expr::Rel(..) | Expr::And(..) | Expr::Or(..) => format!("({expr})"),
+            expr => format!("{expr}"),
+        };
+        match self {
+            Expr::Int(int) => write!(f, "{int}"),
+            Expr::Var(name) => write!(f, "{name}"),
+            Expr::Deref(expr) => write!(f, "*{}", operand(expr)),
+            Expr::Neg(expr) => write!(f, "-{}", operand(expr)),
+            Expr::Arith(lhs, op, rhs) => write!(f, "{} {op} {}", operand(lhs), operand(rhs)),
+            Expr::Rel(lhs, op, rhs) => write!(f, "{} {op} {}", operand(lhs), operand(rhs)),
+            Expr::And(lhs, rhs) => write!(f, "{} && {}", operand(lhs), operand(rhs)),
+            Expr::Or(lhs, rhs) => write!(f, "{} || {}", operand(lhs), operand(rhs)),
+        }
+    }
+}
+
+fn truth(value: &Value) -> bool {
+    *value != Value::new_int(0)
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let text = String::from(text);
+        catch(|| {
+            let mut parser = ExprParser {
+                lexer: Lexer::from(text),
+            };
+            let expr = parser.parse_or()?;
+            parser.expect(Token::TokEOF)?;
+            Ok(expr)
+        })
+        .map_err(|err| match err.strip_prefix("lex error: ") {
+            Some(msg) => String::from(msg),
+            None => err,
+        })?
+    }
+
+    pub fn eval(&self, scope: &Scope) -> Result<Value, String> {
+        match self {
+            Expr::Int(int) => Ok(Value::new_int(*int)),
+            Expr::Var(name) => {
+                let func = &scope.program.funcs[scope.frame.func];
+                match (func.var(name), name.as_str()) {
+                    (Some(id), _) => Ok(scope.frame.get(&id).cloned().unwrap_or_default()),
+                    (None, "depth") => Ok(Value::new_int(scope.env.stack().len() as i64)),
+                    (None, "icount") => Ok(Value::new_int(scope.icount as i64)),
+                    (None, _) => Err(format!("no variable {name} in {}", func.name)),
+                }
+            }
+            Expr::Deref(ptr) => match ptr.eval(scope)? {
+                ptr @ Value::ValPtr { .. } => ptr
+                    .peek()
+                    .map(Value::new_int)
+                    .ok_or_else(|| format!("{self} is out of bounds")),
+                Value::ValInt(_) => Err(format!("cannot dereference {ptr}, not a pointer")),
+            },
+            Expr::Neg(expr) => match expr.eval(scope)? {
+                Value::ValInt(int) => Ok(Value::new_int(int.wrapping_neg())),
+                _ => Err(String::from("cannot negate a pointer")),
+            },
+            Expr::Arith(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(scope)?, rhs.eval(scope)?);
+                match (op, &lhs, &rhs) {
+                    (_, _, Value::ValPtr { .. })
+                    | (ArithOp::Mul | ArithOp::Div, Value::ValPtr { .. }, _) => {
+                        Err(format!("invalid pointer arithmetic in {self}"))
+                    }
+                    (ArithOp::Div, _, Value::ValInt(0)) => Err(String::from("division by zero")),
+                    (ArithOp::Add, ..) => Ok(lhs + rhs),
+                    (ArithOp::Sub, ..) => Ok(lhs - rhs),
+                    (ArithOp::Mul, ..) => Ok(lhs * rhs),
+                    (ArithOp::Div, ..) => Ok(lhs / rhs),
+                }
+            }
+            Expr::Rel(lhs, op, rhs) => {
+                let holds = op.eval(&lhs.eval(scope)?, &rhs.eval(scope)?);
+                Ok(Value::new_int(holds as i64))
+            }
+            Expr::And(lhs, rhs) => Ok(Value::new_int(
+                (truth(&lhs.eval(scope)?) && truth(&rhs.eval(scope)?)) as i64,
+            )),
+            Expr::Or(lhs, rhs) => Ok(Value::new_int(
+                (truth(&lhs.eval(scope)?) || truth(&rhs.eval(scope)?)) as i64,
+            )),
+        }
+    }
+
+    /// Whether the expression evaluates to anything but 0.
+    pub fn holds(&self, scope: &Scope) -> Result<bool, String> {
+        self.eval(scope).map(|value| truth(&value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{exec::Interpreter, parser::Parser};
+
+    use super::*;
+
+    #[test]
+    fn test_eval() {
+        let program = Parser::from(
+            "FUNCTION main :
+             DEC arr 8
+             p := &arr
+             q := p + #4
+             *q := #7
+             n := #3
+             RETURN #0",
+        )
+        .parse();
+        let mut interpreter = Interpreter::new(program, "".as_bytes(), Vec::new());
+        for _ in 0..5 {
+            interpreter.tick();
+        }
+        let eval = |text: &str| {
+            let scope = Scope {
+                program: interpreter.program(),
+                env: interpreter.env(),
+                frame: interpreter.env().top_frame(),
+                icount: interpreter.instr_cnt(),
+            };
+            Expr::parse(text)?.eval(&scope).map(|value| match value {
+                Value::ValInt(int) => int,
+                Value::ValPtr { ptr, .. } => ptr as i64 + 1000,
+            })
+        };
+        assert_eq!(eval("n == 3 && depth == 1"), Ok(1));
+        assert_eq!(eval("n == 3 && icount > 5 || n < #0"), Ok(0));
+        assert_eq!(eval("-n * (2 + 1) - 1"), Ok(-10));
+        assert_eq!(eval("*(p+4) + *p"), Ok(7));
+        assert_eq!(eval("q - 4"), Ok(1000));
+        assert_eq!(
+            eval("*(p+8)"),
+            Err(String::from("*(p + 8) is out of bounds"))
+        );
+        assert_eq!(
+            eval("*n"),
+            Err(String::from("cannot dereference n, not a pointer"))
+        );
+        assert_eq!(eval("n / 0"), Err(String::from("division by zero")));
+        assert_eq!(
+            eval("p * 2"),
+            Err(String::from("invalid pointer arithmetic in p * 2"))
+        );
+        assert_eq!(eval("m"), Err(String::from("no variable m in main")));
+        assert_eq!(eval("n =="), Err(String::from("unexpected TokEOF")));
+        assert_eq!(eval("n | 1"), Err(String::from("unexpected character '|'")));
+        assert_eq!(eval("n = 1"), Err(String::from("unexpected character '='")));
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use crate::{env::Env, exec::Hook, instr::Program};
+
+/// Counts instructions by the call stack they ran in, for flamegraphs.
+pub struct Folded {
+    /// Index into `counts` of every stack seen, as function ids from the
+    /// outermost call.
+    index: HashMap<Vec<usize>, usize>,
+    counts: Vec<(Vec<usize>, usize)>,
+    /// Entry of `counts` for the stack of the instruction being executed.
+    current: Option<usize>,
+}
+
+impl Folded {
+    pub fn new() -> Self {
+        Folded {
+            index: HashMap::new(),
+            counts: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// One `outer;inner count` line per stack, as read by flamegraph tools.
+    pub fn report(&self, program: &Program) -> String {
+        let mut lines: Vec<String> = self
+            .counts
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(stack, count)| {
+                let names: Vec<&str> = stack
+                    .iter()
+                    .map(|&func| program.funcs[func].name.as_str())
+                    .collect();
+                format!("{} {count}", names.join(";"))
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+impl Hook for Folded {
+    fn before(&mut self, _program: &Program, env: &Env) {
+        let stack = env.stack();
+        // A step pushes or pops at most one frame, so the stack is unchanged
+        // if its depth and top are.
+        if let Some(current) = self.current {
+            let prev = &self.counts[current].0;
+            if prev.len() == stack.len() && prev.last() == stack.last().map(|frame| &frame.func) {
+                return;
+            }
+        }
+        let funcs: Vec<usize> = stack.iter().map(|frame| frame.func).collect();
+        let counts = &mut self.counts;
+        let current = *self.index.entry(funcs).or_insert_with_key(|funcs| {
+            counts.push((funcs.clone(), 0));
+            counts.len() - 1
+        });
+        self.current = Some(current)
+    }
+
+    fn after(&mut self, _program: &Program, _env: &Env) {
+        if let Some(current) = self.current {
+            self.counts[current].1 += 1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::tests::run_with;
+
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let (program, folded, instr_cnt) = run_with(
+            "FUNCTION fact :
+             PARAM n
+             IF n > #1 GOTO rec
+             RETURN #1
+             LABEL rec :
+             m := n - #1
+             r := CALL fact(m)
+             r := r * n
+             RETURN r
+
+             FUNCTION main :
+             x := CALL fact(#3)
+             y := CALL fact(#1)
+             WRITE x
+             RETURN #0",
+            |_| Folded::new(),
+        );
+        let report = folded.report(&program);
+        assert_eq!(
+            report,
+            "main 5
+main;fact 11
+main;fact;fact 8
+main;fact;fact;fact 3
+"
+        );
+        let total: usize = report
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().1.parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(total, instr_cnt);
+    }
+}
@@ -1,9 +1,13 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{hash_map::Entry, HashMap, VecDeque},
     fmt::{Display, Formatter},
 };
 
-use crate::env::Frame;
+use crate::{
+    env::{Env, Frame},
+    intrinsic::Intrinsic,
+    value::Value,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Operand {
@@ -20,29 +24,33 @@ impl Operand {
     }
 }
 
-struct Binding {
+/// The variables of a function, numbered in order of first appearance.
+#[derive(Debug, Clone, Default)]
+pub struct Binding {
     map: HashMap<String, usize>,
-    id: usize,
+    names: Vec<String>,
 }
 
 impl Binding {
     pub fn new() -> Binding {
-        Binding {
-            map: HashMap::new(),
-            id: 0,
-        }
+        Binding::default()
     }
 
     pub fn insert(&mut self, name: &String) {
-        if self.map.get(name).is_none() {
-            self.map.insert(name.clone(), self.id);
-            self.id += 1;
+        if !self.map.contains_key(name) {
+            self.map.insert(name.clone(), self.names.len());
+            self.names.push(name.clone());
         }
     }
 
-    pub fn get(&self, name: &String) -> Option<usize> {
+    pub fn get(&self, name: &str) -> Option<usize> {
         self.map.get(name).copied()
     }
+
+    /// Names of all variables, indexed by register id.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
 }
 
 impl From<i64> for Operand {
@@ -78,8 +86,8 @@ impl From<String> for Operand {
 impl Display for Operand {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Reg { name, id } => write!(f, "{name}_{id}"),
-            Self::Imm(int) => write!(f, "{int}"),
+            Self::Reg { name, .. } => write!(f, "{name}"),
+            Self::Imm(int) => write!(f, "#{int}"),
         }
     }
 }
@@ -113,6 +121,19 @@ pub enum RelOp {
     NE,
 }
 
+impl RelOp {
+    pub fn eval(&self, x: &Value, y: &Value) -> bool {
+        match self {
+            Self::LT => x < y,
+            Self::LE => x <= y,
+            Self::GT => x > y,
+            Self::GE => x >= y,
+            Self::EQ => x == y,
+            Self::NE => x != y,
+        }
+    }
+}
+
 impl Display for RelOp {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -145,17 +166,23 @@ pub enum Instr {
         name: String,
         id: usize,
     },
-    Return(Operand),
+    Return(Option<Operand>),
     Dec(Operand, i64),
     Arg(Operand),
     Call {
-        x: Operand,
+        x: Option<Operand>,
         name: String,
         id: usize,
     },
     Param(Operand),
     Read(Operand),
     Write(Operand),
+    Assert {
+        x: Operand,
+        op: RelOp,
+        y: Operand,
+        msg: Option<String>,
+    },
 }
 
 impl Instr {
@@ -166,6 +193,46 @@ impl Instr {
         }
     }
 
+    /// Variables this instruction reads, in the order they are written.
+    pub fn reads(&self) -> Vec<&Operand> {
+        let operands = match self {
+            Self::Assign(_, y) | Self::Deref(_, y) | Self::Load(_, y) => vec![y],
+            Self::Arith(_, y, _, z) => vec![y, z],
+            Self::Store(x, y) | Self::Cond { x, y, .. } | Self::Assert { x, y, .. } => vec![x, y],
+            Self::Return(Some(x)) | Self::Arg(x) | Self::Write(x) => vec![x],
+            _ => Vec::new(),
+        };
+        operands
+            .into_iter()
+            .filter(|operand| matches!(operand, Operand::Reg { .. }))
+            .collect()
+    }
+
+    /// Variable this instruction writes, if any. A `CALL` to a function
+    /// that is not an intrinsic only writes it when the callee returns.
+    pub fn dest(&self) -> Option<&Operand> {
+        match self {
+            Self::Assign(x, _)
+            | Self::Arith(x, ..)
+            | Self::Deref(x, _)
+            | Self::Load(x, _)
+            | Self::Dec(x, _)
+            | Self::Call { x: Some(x), .. }
+            | Self::Param(x)
+            | Self::Read(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Whether an `IF` about to run in `env` jumps, or `None` for other
+    /// instructions.
+    pub fn jumps(&self, env: &Env) -> Option<bool> {
+        match self {
+            Self::Cond { x, op, y, .. } => Some(op.eval(&env.get(x), &env.get(y))),
+            _ => None,
+        }
+    }
+
     fn bind(&mut self, bind: &mut Binding) {
         match self {
             Self::Assign(x, y) => {
@@ -189,14 +256,14 @@ impl Instr {
                 x.init(bind);
                 y.init(bind)
             }
-            Self::Cond { x, y, .. } => {
+            Self::Cond { x, y, .. } | Self::Assert { x, y, .. } => {
                 x.init(bind);
                 y.init(bind)
             }
-            Self::Return(x) => x.init(bind),
+            Self::Return(Some(x)) => x.init(bind),
             Self::Dec(x, _) => x.init(bind),
             Self::Arg(x) => x.init(bind),
-            Self::Call { x, .. } => x.init(bind),
+            Self::Call { x: Some(x), .. } => x.init(bind),
             Self::Param(x) => x.init(bind),
             Self::Read(x) => x.init(bind),
             Self::Write(x) => x.init(bind),
@@ -214,15 +281,23 @@ impl Display for Instr {
             Self::Store(x, y) => write!(f, "*{x} := {y}"),
             Self::Load(x, y) => write!(f, "{x} := *{y}"),
             Self::Label(name) => write!(f, "LABEL {name} :"),
-            Self::Goto { name, .. } => write!(f, "GOTO {name} "),
+            Self::Goto { name, .. } => write!(f, "GOTO {name}"),
             Self::Cond { x, op, y, name, .. } => write!(f, "IF {x} {op} {y} GOTO {name}"),
-            Self::Return(x) => write!(f, "RETURN {x}"),
+            Self::Return(Some(x)) => write!(f, "RETURN {x}"),
+            Self::Return(None) => write!(f, "RETURN"),
             Self::Dec(x, size) => write!(f, "DEC {x} {size}"),
             Self::Arg(x) => write!(f, "ARG {x}"),
-            Self::Call { x, name, .. } => write!(f, "{x} := CALL {name}"),
+            Self::Call {
+                x: Some(x), name, ..
+            } => write!(f, "{x} := CALL {name}"),
+            Self::Call { x: None, name, .. } => write!(f, "CALL {name}"),
             Self::Param(x) => write!(f, "PARAM {x}"),
             Self::Read(x) => write!(f, "READ {x}"),
             Self::Write(x) => write!(f, "WRITE {x}"),
+            Self::Assert { x, op, y, msg } => match msg {
+                Some(msg) => write!(f, "ASSERT {x} {op} {y} \"{msg}\""),
+                None => write!(f, "ASSERT {x} {op} {y}"),
+            },
         }
     }
 }
@@ -231,12 +306,42 @@ impl Display for Instr {
 pub struct Func {
     pub name: String,
     pub body: Vec<Instr>,
+    /// Source line of each instruction in `body`.
+    pub lines: Vec<usize>,
     pub nreg: usize,
+    /// Names of the variables, kept from binding them to registers.
+    pub binding: Binding,
+    /// Number of `PARAM`s in `body`.
+    pub nparam: usize,
     pub id: usize,
+    /// Index into `Program::files` of the file this function was parsed from.
+    pub file: usize,
+    /// File-private functions are only callable from their own file.
+    pub private: bool,
+    /// Set for host-implemented functions, which have an empty body.
+    pub builtin: Option<Intrinsic>,
 }
 
 impl Func {
-    pub fn init(&mut self) {
+    pub fn new(name: &str) -> Func {
+        Func {
+            name: String::from(name),
+            body: Vec::new(),
+            lines: Vec::new(),
+            nreg: 0,
+            binding: Binding::new(),
+            nparam: 0,
+            id: 0,
+            file: 0,
+            private: false,
+            builtin: None,
+        }
+    }
+}
+
+impl Func {
+    /// Resolves labels and binds variables; `file` is named in errors.
+    pub fn init(&mut self, file: &str) {
         let mut map = HashMap::new();
 
         self.body.iter().enumerate().for_each(|(id, instr)| {
@@ -245,47 +350,144 @@ impl Func {
             }
         });
 
-        let bind = &mut Binding::new();
+        let mut bind = Binding::new();
         for instr in &mut self.body {
             match instr {
                 Instr::Goto { name, .. } => {
-                    let id = *map.get(name).unwrap();
+                    let id = *map.get(name).unwrap_or_else(|| {
+                        panic!("undefined label {name} in {} ({file})", self.name)
+                    });
                     *instr = Instr::Goto {
                         name: name.clone(),
                         id,
                     }
                 }
                 Instr::Cond { id, name, .. } => {
-                    *id = *map.get(name).unwrap_or_else(|| panic!("{name}"));
+                    *id = *map.get(name).unwrap_or_else(|| {
+                        panic!("undefined label {name} in {} ({file})", self.name)
+                    });
                 }
                 _ => (),
             }
         }
 
         for instr in &mut self.body {
-            instr.bind(bind);
+            instr.bind(&mut bind);
+        }
+        self.nreg = bind.names().len();
+        self.binding = bind;
+        self.nparam = self
+            .body
+            .iter()
+            .filter(|instr| matches!(instr, Instr::Param(_)))
+            .count()
+    }
+
+    pub fn var(&self, name: &str) -> Option<usize> {
+        self.binding.get(name)
+    }
+
+    /// Number of arguments a call to this function must pass.
+    pub fn arity(&self) -> usize {
+        self.builtin.map_or(self.nparam, |builtin| builtin.arity())
+    }
+
+    /// Finds calls whose number of preceding `ARG`s is known and differs from
+    /// the callee's arity.
+    ///
+    /// The count is only known on straight-line code, so it is reset at
+    /// labels and after jumps.
+    pub fn check_args(&self, program: &Program) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut nargs = Some(0);
+        for (pc, instr) in self.body.iter().enumerate() {
+            match instr {
+                Instr::Arg(_) => nargs = nargs.map(|n| n + 1),
+                Instr::Label(_) | Instr::Goto { .. } | Instr::Return(_) => nargs = None,
+                Instr::Call { id, .. } => {
+                    let callee = &program.funcs[*id];
+                    match nargs {
+                        Some(n) if n != callee.arity() => errors.push(format!(
+                            "{} passes {n} arguments to {}, which takes {}, at {}:{}",
+                            self.name,
+                            callee.name,
+                            callee.arity(),
+                            program.file(self),
+                            self.lines.get(pc).unwrap_or(&0)
+                        )),
+                        _ => (),
+                    }
+                    nargs = Some(0)
+                }
+                _ => (),
+            }
         }
-        self.nreg = bind.id
+        errors
     }
 }
 
-impl Display for Func {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let name = &self.name;
-        writeln!(f, "FUNCTION {name} :")?;
+/// How calls are written out in listings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallStyle {
+    /// `ARG b`, `ARG a` and `x := CALL f`, as executed.
+    Arg,
+    /// `x := CALL f(a, b)`, folding the `ARG`s right before each call.
+    Inline,
+}
+
+impl Func {
+    pub fn listing(&self, style: CallStyle) -> String {
+        let mut listing = String::new();
+        if self.private {
+            listing.push_str("PRIVATE ");
+        }
+        listing.push_str(&format!("FUNCTION {} :\n", self.name));
+        let mut args = Vec::new();
         for instr in &self.body {
             match instr {
-                Instr::Label(_) => writeln!(f, "{instr}")?,
-                _ => writeln!(f, "  {instr}")?,
+                Instr::Arg(x) if style == CallStyle::Inline => {
+                    args.push(x);
+                    continue;
+                }
+                Instr::Call { x, name, .. } if style == CallStyle::Inline => {
+                    let args: Vec<String> = args.drain(..).rev().map(|x| x.to_string()).collect();
+                    let args = args.join(", ");
+                    listing.push_str(&match x {
+                        Some(x) => format!("  {x} := CALL {name}({args})\n"),
+                        None => format!("  CALL {name}({args})\n"),
+                    });
+                    continue;
+                }
+                _ => (),
+            }
+            for x in args.drain(..) {
+                listing.push_str(&format!("  ARG {x}\n"));
+            }
+            // Strings in IR have no escapes, so these could not be read back.
+            if let Instr::Assert { msg: Some(msg), .. } = instr {
+                if msg.contains(['"', '\n']) {
+                    panic!("ASSERT message {msg:?} cannot be written as IR")
+                }
+            }
+            match instr {
+                Instr::Label(_) => listing.push_str(&format!("{instr}\n")),
+                _ => listing.push_str(&format!("  {instr}\n")),
             }
         }
-        Ok(())
+        listing
+    }
+}
+
+impl Display for Func {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.listing(CallStyle::Arg))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Program {
     pub funcs: VecDeque<Func>,
+    pub files: Vec<String>,
     pub entry: usize,
 }
 
@@ -297,47 +499,163 @@ impl Program {
     pub fn new() -> Program {
         Program {
             funcs: VecDeque::new(),
+            files: Vec::new(),
             entry: 0,
         }
     }
 
-    pub fn fetch(&self, frame: &Frame) -> Instr {
-        self.funcs[frame.func].body[frame.pc].clone()
+    /// Links separately parsed programs into one, in the given order.
+    pub fn link(programs: Vec<Program>) -> Program {
+        let mut linked = Program::new();
+        for program in programs {
+            let base = linked.files.len();
+            linked.files.extend(program.files);
+            linked
+                .funcs
+                .extend(program.funcs.into_iter().map(|mut func| {
+                    func.file += base;
+                    func
+                }));
+        }
+        linked
+    }
+
+    pub fn file(&self, func: &Func) -> &str {
+        self.files
+            .get(func.file)
+            .map_or("<input>", |file| file.as_str())
+    }
+
+    /// Reports calls with statically known argument count mismatches.
+    pub fn check_args(&self) -> Vec<String> {
+        self.funcs
+            .iter()
+            .flat_map(|func| func.check_args(self))
+            .collect()
+    }
+
+    /// Describes where `frame` currently is, as `func (file:line)`.
+    pub fn loc(&self, frame: &Frame) -> String {
+        self.loc_at(frame.func, frame.pc)
+    }
+
+    /// Named variables of `frame` with their values, in register order.
+    pub fn vars<'a>(&'a self, frame: &'a Frame) -> Vec<(&'a str, &'a Value)> {
+        self.funcs[frame.func]
+            .binding
+            .names()
+            .iter()
+            .enumerate()
+            .filter_map(|(id, name)| Some((name.as_str(), frame.get(&id)?)))
+            .collect()
+    }
+
+    pub fn loc_at(&self, func: usize, pc: usize) -> String {
+        let func = &self.funcs[func];
+        let file = self.file(func);
+        match func.lines.get(pc) {
+            Some(line) => format!("{} ({file}:{line})", func.name),
+            None => format!("{} ({file})", func.name),
+        }
+    }
+
+    /// Fetches the instruction at `frame`, or `None` past the end of the body.
+    pub fn fetch(&self, frame: &Frame) -> Option<Instr> {
+        self.funcs[frame.func].body.get(frame.pc).cloned()
     }
 
     pub fn init(&mut self) {
-        self.funcs.iter_mut().for_each(|func| func.init());
+        let files = &self.files;
+        self.funcs.iter_mut().for_each(|func| {
+            let file = files.get(func.file).map_or("<input>", |file| file.as_str());
+            func.init(file)
+        });
 
-        let mut map: HashMap<String, usize> = HashMap::new();
+        let mut public: HashMap<String, usize> = HashMap::new();
+        let mut private: HashMap<(usize, String), usize> = HashMap::new();
         for (id, func) in self.funcs.iter().enumerate() {
-            map.insert(func.name.clone(), id);
+            let key = (func.file, func.name.clone());
+            let prev = match public.get(&func.name) {
+                Some(&prev) if !func.private || self.funcs[prev].file == func.file => Some(prev),
+                _ => private.get(&key).copied(),
+            };
+            if let Some(prev) = prev {
+                panic!(
+                    "duplicate function {}: defined in {} and {}",
+                    func.name,
+                    self.file(&self.funcs[prev]),
+                    self.file(func)
+                );
+            }
+            if func.private {
+                private.insert(key, id);
+            } else {
+                public.insert(func.name.clone(), id);
+            }
         }
 
-        self.entry = *map
+        self.entry = *public
             .get(&String::from("main"))
             .expect("no main function found");
 
         self.funcs
             .iter_mut()
-            .for_each(|func| func.id = *map.get(&func.name).unwrap());
+            .enumerate()
+            .for_each(|(id, func)| func.id = id);
+
+        let builtins: Vec<Intrinsic> = self
+            .funcs
+            .iter()
+            .flat_map(|func| func.body.iter())
+            .filter_map(|instr| match instr {
+                Instr::Call { name, .. } if !public.contains_key(name) => {
+                    Intrinsic::from_name(name)
+                }
+                _ => None,
+            })
+            .collect();
+        for builtin in builtins {
+            if let Entry::Vacant(entry) = public.entry(builtin.to_string()) {
+                let mut func = Func::new(entry.key());
+                func.id = self.funcs.len();
+                func.builtin = Some(builtin);
+                entry.insert(func.id);
+                self.funcs.push_back(func);
+            }
+        }
 
-        self.funcs
-            .iter_mut()
-            .flat_map(|func| func.body.iter_mut())
-            .for_each(|instr| {
+        let files = &self.files;
+        self.funcs.iter_mut().for_each(|func| {
+            let file = func.file;
+            for instr in &mut func.body {
                 if let Instr::Call { name, id, .. } = instr {
-                    *id = *map.get(name).unwrap()
+                    *id = *private
+                        .get(&(file, name.clone()))
+                        .or_else(|| public.get(name))
+                        .unwrap_or_else(|| {
+                            let file = files.get(file).map_or("<input>", |file| file.as_str());
+                            panic!("undefined function {name} called in {file}")
+                        })
                 }
-            });
+            }
+        });
+    }
+}
+
+impl Program {
+    pub fn listing(&self, style: CallStyle) -> String {
+        self.funcs
+            .iter()
+            .filter(|func| func.builtin.is_none())
+            .map(|func| func.listing(style))
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
 
 impl Display for Program {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        for func in &self.funcs {
-            writeln!(f, "{func}\n")?
-        }
-        Ok(())
+        write!(f, "{}", self.listing(CallStyle::Arg))
     }
 }
 
@@ -386,14 +704,182 @@ mod tests {
         assert_eq!(
             program.funcs[1].body[2],
             Instr::Call {
-                x: Operand::Reg {
+                x: Some(Operand::Reg {
                     name: String::from("s"),
                     id: 1
-                },
+                }),
                 name: String::from("foo"),
                 id: 0
             }
         );
         assert_eq!(program.entry, 1);
+
+        let foo = &program.funcs[0];
+        assert_eq!(foo.binding.names(), ["n", "i", "s"]);
+        assert_eq!(foo.var("s"), Some(2));
+        let mut frame = Frame::new(foo);
+        frame.set(&0, &Value::new_int(5));
+        let vars: Vec<String> = program
+            .vars(&frame)
+            .iter()
+            .map(|(name, value)| format!("{name} = {value}"))
+            .collect();
+        assert_eq!(vars, ["n = 5", "i = 0", "s = 0"]);
+    }
+
+    fn link(files: &[(&str, &str)]) -> Program {
+        let mut program = Program::link(
+            files
+                .iter()
+                .map(|(file, code)| Parser::with_file(file, code).parse())
+                .collect(),
+        );
+        program.init();
+        program
+    }
+
+    #[test]
+    fn test_link() {
+        let program = link(&[
+            (
+                "lib.ir",
+                "PRIVATE FUNCTION helper :
+                 RETURN #1
+
+                 FUNCTION inc :
+                 PARAM n
+                 t := CALL helper
+                 r := n + t
+                 RETURN r",
+            ),
+            (
+                "main.ir",
+                "FUNCTION helper :
+                 RETURN #2
+
+                 FUNCTION main :
+                 ARG #1
+                 x := CALL inc
+                 y := CALL helper
+                 RETURN #0",
+            ),
+        ]);
+        assert_eq!(program.files, vec!["lib.ir", "main.ir"]);
+        assert_eq!(program.entry, 3);
+        assert_eq!(program.file(&program.funcs[2]), "main.ir");
+        assert!(matches!(
+            program.funcs[1].body[1],
+            Instr::Call { id: 0, .. }
+        ));
+        assert!(matches!(
+            program.funcs[3].body[1],
+            Instr::Call { id: 1, .. }
+        ));
+        assert!(matches!(
+            program.funcs[3].body[2],
+            Instr::Call { id: 2, .. }
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate function foo: defined in a.ir and b.ir")]
+    fn test_link_duplicate() {
+        link(&[
+            ("a.ir", "FUNCTION foo :\nRETURN #0"),
+            (
+                "b.ir",
+                "FUNCTION foo :\nRETURN #0\nFUNCTION main :\nRETURN #0",
+            ),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "undefined label nowhere in main (b.ir)")]
+    fn test_link_undefined_label() {
+        link(&[
+            ("a.ir", "FUNCTION foo :\nRETURN #0"),
+            (
+                "b.ir",
+                "FUNCTION main :\nIF #1 > #0 GOTO nowhere\nRETURN #0",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_check_args() {
+        let program = link(&[(
+            "a.ir",
+            "FUNCTION add :
+             PARAM x
+             PARAM y
+             RETURN #0
+
+             FUNCTION main :
+             ARG #1
+             t := CALL add
+             ARG #1
+             LABEL l :
+             ARG #2
+             t := CALL add
+             ARG #1
+             ARG #2
+             ARG #3
+             t := CALL abs
+             RETURN #0",
+        )]);
+        assert_eq!(
+            program.check_args(),
+            vec![
+                "main passes 1 arguments to add, which takes 2, at a.ir:8",
+                "main passes 3 arguments to abs, which takes 1, at a.ir:16",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listing() {
+        let code = "FUNCTION main :
+  ARG #1
+  t := x + #1
+  ARG t
+  ARG y
+  r := CALL f
+  CALL g
+LABEL l :
+  IF r >= #0 GOTO l
+  RETURN r
+";
+        let sugared = "FUNCTION main :
+  ARG #1
+  t := x + #1
+  r := CALL f(y, t)
+  CALL g()
+LABEL l :
+  IF r >= #0 GOTO l
+  RETURN r
+";
+        let program = Parser::from(code).parse();
+        assert_eq!(program.listing(CallStyle::Arg), code);
+        assert_eq!(program.listing(CallStyle::Inline), sugared);
+        assert_eq!(Parser::from(sugared).parse().listing(CallStyle::Arg), code);
+    }
+
+    #[test]
+    fn test_listing_assert() {
+        let code = r#"FUNCTION main :
+  ASSERT x >= #0 "n\0 can't be négatif"
+  RETURN #0
+"#;
+        assert_eq!(Parser::from(code).parse().listing(CallStyle::Arg), code);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be written as IR")]
+    fn test_listing_quote() {
+        let mut program = Parser::from("FUNCTION main :\nASSERT x >= #0 \"x\"\nRETURN #0").parse();
+        if let Instr::Assert { msg, .. } = &mut program.funcs[0].body[0] {
+            *msg = Some(String::from("say \"hi\""))
+        }
+        program.listing(CallStyle::Arg);
     }
 }
@@ -0,0 +1,108 @@
+use std::{fmt::Display, io::Write};
+
+use crate::value::Value;
+
+/// Host-implemented functions, callable like any IR function when no user
+/// `FUNCTION` has the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intrinsic {
+    Abs,
+    Min,
+    Max,
+    Exit,
+    Assert,
+    Putchar,
+}
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Intrinsic> {
+        match name {
+            "abs" => Some(Self::Abs),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            "exit" => Some(Self::Exit),
+            "assert" => Some(Self::Assert),
+            "putchar" => Some(Self::Putchar),
+            _ => None,
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::Min | Self::Max => 2,
+            Self::Abs | Self::Exit | Self::Assert | Self::Putchar => 1,
+        }
+    }
+
+    /// Calls the intrinsic with `args` in parameter order, i.e. `args[0]` is
+    /// what the first `PARAM` of an IR function would receive.
+    ///
+    /// Returns `Err` with the exit status if the program should stop, which
+    /// only `exit` does. `loc` describes the call site for a failing `assert`.
+    pub fn call(
+        &self,
+        args: Vec<Value>,
+        fout: &mut impl Write,
+        loc: impl FnOnce() -> String,
+    ) -> Result<Value, i32> {
+        let int = |value: &Value| match value {
+            Value::ValInt(int) => *int,
+            Value::ValPtr { .. } => panic!("{self} expects integer arguments"),
+        };
+        let value = match self {
+            Self::Abs => Value::new_int(int(&args[0]).wrapping_abs()),
+            Self::Min => Value::new_int(int(&args[0]).min(int(&args[1]))),
+            Self::Max => Value::new_int(int(&args[0]).max(int(&args[1]))),
+            Self::Exit => return Err(int(&args[0]) as i32),
+            Self::Assert => {
+                if int(&args[0]) == 0 {
+                    panic!("assertion failed with {self}({}) at {}", args[0], loc())
+                }
+                Value::new_int(0)
+            }
+            Self::Putchar => {
+                let ch = int(&args[0]);
+                fout.write_all(&[ch as u8]).expect("write error");
+                Value::new_int(ch)
+            }
+        };
+        Ok(value)
+    }
+}
+
+impl Display for Intrinsic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Abs => write!(f, "abs"),
+            Self::Min => write!(f, "min"),
+            Self::Max => write!(f, "max"),
+            Self::Exit => write!(f, "exit"),
+            Self::Assert => write!(f, "assert"),
+            Self::Putchar => write!(f, "putchar"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call() {
+        let fout = &mut Vec::new();
+        let call = |intrinsic: Intrinsic, args: &[i64], fout: &mut Vec<u8>| {
+            let args = args.iter().map(|&x| Value::new_int(x)).collect();
+            intrinsic.call(args, fout, || String::from("main"))
+        };
+        assert_eq!(call(Intrinsic::Abs, &[-3], fout), Ok(Value::new_int(3)));
+        assert_eq!(call(Intrinsic::Min, &[1, 2], fout), Ok(Value::new_int(1)));
+        assert_eq!(call(Intrinsic::Max, &[1, 2], fout), Ok(Value::new_int(2)));
+        assert_eq!(
+            call(Intrinsic::Putchar, &[65], fout),
+            Ok(Value::new_int(65))
+        );
+        assert_eq!(call(Intrinsic::Exit, &[3], fout), Err(3));
+        assert_eq!(call(Intrinsic::Assert, &[1], fout), Ok(Value::new_int(0)));
+        assert_eq!(fout, b"A");
+    }
+}
@@ -5,13 +5,17 @@ pub struct Lexer {
     char_stream: CharStream,
     curr: Token,
     lineno: usize,
+    /// Source file named in errors, if the input came from one.
+    file: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     TokIden(String),
     TokInt(i64),
+    TokStr(String),
     TokFunc,
+    TokPrivate,
     TokLabel,
     TokIf,
     TokGoto,
@@ -22,6 +26,7 @@ pub enum Token {
     TokParam,
     TokRead,
     TokWrite,
+    TokAssert,
     TokColon,
     TokLT,
     TokLE,
@@ -36,6 +41,11 @@ pub enum Token {
     TokStar,
     TokDiv,
     TokAmp,
+    TokAnd,
+    TokOr,
+    TokLParen,
+    TokRParen,
+    TokComma,
     TokNl,
     TokEOF,
 }
@@ -46,11 +56,31 @@ impl Lexer {
             char_stream: CharStream::from_string(input),
             curr: Token::TokEOF,
             lineno: 1,
+            file: None,
         };
         lexer.consume();
         lexer
     }
 
+    /// Creates a lexer whose errors refer to `file`.
+    pub fn with_file(file: &str, input: String) -> Lexer {
+        let mut lexer = Lexer {
+            char_stream: CharStream::from_string(input),
+            curr: Token::TokEOF,
+            lineno: 1,
+            file: Some(String::from(file)),
+        };
+        lexer.consume();
+        lexer
+    }
+
+    fn error(&self, msg: &str) -> ! {
+        match &self.file {
+            Some(file) => panic!("lex error: {msg} at {file}:{}", self.lineno),
+            None => panic!("lex error: {msg}"),
+        }
+    }
+
     pub fn consume(&mut self) -> (Token, usize) {
         let result = self.peek();
         self.curr = match self.char_stream.peek() {
@@ -66,6 +96,7 @@ impl Lexer {
             }
             Some('0'..='9') => self.lex_int(),
             Some('a'..='z' | 'A'..='Z' | '_') => self.lex_iden(),
+            Some('"') => self.lex_str(),
             Some('#') => {
                 self.char_stream.next();
                 Token::TokSharp
@@ -93,7 +124,7 @@ impl Lexer {
                         self.char_stream.next();
                         Token::TokEQ
                     }
-                    ch => panic!("lex error: {:?}", ch),
+                    _ => self.error("unexpected character '='"),
                 }
             }
             Some('<') => {
@@ -126,9 +157,37 @@ impl Lexer {
                     _ => Token::TokColon,
                 }
             }
+            Some('(') => {
+                self.char_stream.next();
+                Token::TokLParen
+            }
+            Some(')') => {
+                self.char_stream.next();
+                Token::TokRParen
+            }
+            Some(',') => {
+                self.char_stream.next();
+                Token::TokComma
+            }
             Some('&') => {
                 self.char_stream.next();
-                Token::TokAmp
+                match self.char_stream.peek() {
+                    Some('&') => {
+                        self.char_stream.next();
+                        Token::TokAnd
+                    }
+                    _ => Token::TokAmp,
+                }
+            }
+            Some('|') => {
+                self.char_stream.next();
+                match self.char_stream.peek() {
+                    Some('|') => {
+                        self.char_stream.next();
+                        Token::TokOr
+                    }
+                    _ => self.error("unexpected character '|'"),
+                }
             }
             Some('!') => {
                 self.char_stream.next();
@@ -137,10 +196,10 @@ impl Lexer {
                         self.char_stream.next();
                         Token::TokNE
                     }
-                    ch => panic!("lex error: {:?}", ch),
+                    _ => self.error("unexpected character '!'"),
                 }
             }
-            ch => panic!("lex error: {:?}", ch),
+            Some(ch) => self.error(&format!("unexpected character '{ch}'")),
         };
         result
     }
@@ -161,6 +220,21 @@ impl Lexer {
         }
     }
 
+    fn lex_str(&mut self) -> Token {
+        self.char_stream.next();
+        let mut str = String::new();
+        loop {
+            match self.char_stream.next() {
+                Some('"') => return Token::TokStr(str),
+                Some('\n') | None => match &self.file {
+                    Some(_) => self.error("unterminated string"),
+                    None => self.error(&format!("unterminated string at line: {}", self.lineno)),
+                },
+                Some(ch) => str.push(ch),
+            }
+        }
+    }
+
     fn lex_iden(&mut self) -> Token {
         let mut iden = String::new();
         loop {
@@ -178,6 +252,7 @@ impl Lexer {
         }
         match iden.as_str() {
             "FUNCTION" => Token::TokFunc,
+            "PRIVATE" => Token::TokPrivate,
             "LABEL" => Token::TokLabel,
             "IF" => Token::TokIf,
             "GOTO" => Token::TokGoto,
@@ -188,6 +263,7 @@ impl Lexer {
             "PARAM" => Token::TokParam,
             "READ" => Token::TokRead,
             "WRITE" => Token::TokWrite,
+            "ASSERT" => Token::TokAssert,
             _ => Token::TokIden(iden),
         }
     }
@@ -231,4 +307,32 @@ mod tests {
         assert_eq!(lexer.consume().0, Token::TokEOF);
         assert_eq!(lexer.consume().0, Token::TokEOF);
     }
+
+    #[test]
+    fn test_str() {
+        let mut lexer = Lexer::from(String::from("ASSERT x \"x is 0\""));
+        assert_eq!(lexer.consume().0, Token::TokAssert);
+        assert_eq!(lexer.consume().0, Token::TokIden(String::from("x")));
+        assert_eq!(lexer.consume().0, Token::TokStr(String::from("x is 0")));
+        assert_eq!(lexer.consume().0, Token::TokEOF);
+    }
+
+    #[test]
+    fn test_logic() {
+        let mut lexer = Lexer::from(String::from("&x && y || z"));
+        assert_eq!(lexer.consume().0, Token::TokAmp);
+        assert_eq!(lexer.consume().0, Token::TokIden(String::from("x")));
+        assert_eq!(lexer.consume().0, Token::TokAnd);
+        assert_eq!(lexer.consume().0, Token::TokIden(String::from("y")));
+        assert_eq!(lexer.consume().0, Token::TokOr);
+        assert_eq!(lexer.consume().0, Token::TokIden(String::from("z")));
+        assert_eq!(lexer.consume().0, Token::TokEOF);
+    }
+
+    #[test]
+    #[should_panic(expected = "lex error: unexpected character '@' at a.ir:2")]
+    fn test_error_file() {
+        let mut lexer = Lexer::with_file("a.ir", String::from("x\n@"));
+        while lexer.consume().0 != Token::TokEOF {}
+    }
 }
@@ -1,33 +1,470 @@
+mod branches;
+mod breakpoint;
+mod callgraph;
+mod compare;
+mod cost;
+mod coverage;
+mod dap;
+mod debug;
 mod env;
 mod exec;
+mod expr;
+mod folded;
 mod instr;
+mod intrinsic;
 mod lexer;
 mod parser;
+mod profile;
+mod ranges;
+mod repl;
+mod stats;
+mod trace;
 mod value;
 
-use clap::{arg, Command};
+use clap::{arg, parser::ValuesRef, ArgAction, ArgMatches, Command};
 use parser::Parser;
-use std::{fs, io};
+use std::{
+    fs,
+    io::{self, BufWriter, Read, Write},
+    ops::Range,
+};
 
-use crate::exec::Interpreter;
+use crate::{
+    branches::Branches,
+    callgraph::CallGraph,
+    compare::{compare, Outcome},
+    cost::{Cost, CostModel},
+    coverage::{Coverage, CoverageFormat},
+    dap::Server,
+    debug::Debugger,
+    exec::{Hook, ImplicitReturn, Interpreter},
+    folded::Folded,
+    instr::{CallStyle, Program},
+    profile::{Profile, ProfileFormat},
+    ranges::{Ranges, RangesFormat},
+    repl::Repl,
+    stats::{Stats, StatsFormat},
+    trace::{parse_range, Trace, TraceFormat},
+};
+
+fn load(files: Option<ValuesRef<String>>) -> Program {
+    let files = match files {
+        Some(files) => files,
+        None => panic!("arg error"),
+    };
+    Program::link(
+        files
+            .map(|file| {
+                let cont = fs::read_to_string(file).expect("file error");
+                Parser::with_file(file, cont.as_str()).parse()
+            })
+            .collect(),
+    )
+}
+
+fn implicit_return(matches: &ArgMatches) -> ImplicitReturn {
+    match matches
+        .get_one::<String>("implicit-return")
+        .map(String::as_str)
+    {
+        Some("zero") => ImplicitReturn::Zero,
+        _ => ImplicitReturn::Error,
+    }
+}
+
+/// Opens `path` to write a report to, where `-` is stderr.
+fn report(path: &str) -> Box<dyn Write> {
+    match path {
+        "-" => Box::new(BufWriter::new(io::stderr())),
+        path => Box::new(BufWriter::new(fs::File::create(path).expect("file error"))),
+    }
+}
+
+/// The value of `--{flag}-format`, if the flag has one and it was given.
+fn format<'a>(matches: &'a ArgMatches, flag: &str) -> Option<&'a str> {
+    matches
+        .try_get_one::<String>(&format!("{flag}-format"))
+        .ok()
+        .flatten()
+        .map(String::as_str)
+}
+
+/// A hook that is written to a file as a report once the program stops.
+trait Report: Hook {
+    /// The report in `format`, or in the default format if `None`.
+    fn report(&self, program: &Program, format: Option<&str>) -> String;
+}
+
+impl Report for Profile {
+    fn report(&self, program: &Program, format: Option<&str>) -> String {
+        let format = match format {
+            Some("json") => ProfileFormat::Json,
+            _ => ProfileFormat::Text,
+        };
+        Profile::report(self, program, format)
+    }
+}
+
+impl Report for Folded {
+    fn report(&self, program: &Program, _format: Option<&str>) -> String {
+        Folded::report(self, program)
+    }
+}
+
+impl Report for Coverage {
+    fn report(&self, program: &Program, format: Option<&str>) -> String {
+        let format = match format {
+            Some("lcov") => CoverageFormat::Lcov,
+            _ => CoverageFormat::Text,
+        };
+        Coverage::report(self, program, format)
+    }
+}
+
+impl Report for Branches {
+    fn report(&self, program: &Program, _format: Option<&str>) -> String {
+        Branches::report(self, program)
+    }
+}
+
+impl Report for CallGraph {
+    fn report(&self, program: &Program, _format: Option<&str>) -> String {
+        CallGraph::report(self, program)
+    }
+}
+
+impl Report for Stats {
+    fn report(&self, program: &Program, format: Option<&str>) -> String {
+        let format = match format {
+            Some("json") => StatsFormat::Json,
+            _ => StatsFormat::Text,
+        };
+        Stats::report(self, program, format)
+    }
+}
+
+impl Report for Ranges {
+    fn report(&self, program: &Program, format: Option<&str>) -> String {
+        let format = match format {
+            Some("json") => RangesFormat::Json,
+            _ => RangesFormat::Text,
+        };
+        Ranges::report(self, program, format)
+    }
+}
+
+/// Creates the hook of a report for a program.
+type NewReport = fn(&Program) -> Box<dyn Report>;
+
+/// Reports by the flag that asks for them, with how to create their hook.
+const REPORTS: [(&str, NewReport); 7] = [
+    ("profile", |program| Box::new(Profile::new(program))),
+    ("folded", |_| Box::new(Folded::new())),
+    ("coverage", |program| Box::new(Coverage::new(program))),
+    ("branches", |program| Box::new(Branches::new(program))),
+    ("callgraph", |_| Box::new(CallGraph::new())),
+    ("stats", |program| Box::new(Stats::new(program))),
+    ("ranges", |_| Box::new(Ranges::new())),
+];
+
+/// Runs the program freely, observed by the hooks asked for in `matches`.
+fn exec<T: Read>(interpreter: &mut Interpreter<T, io::Stdout>, matches: &ArgMatches) -> usize {
+    let mut trace = matches.get_one::<String>("trace").map(|path| {
+        let format = match format(matches, "trace") {
+            Some("jsonl") => TraceFormat::Jsonl,
+            _ => TraceFormat::Text,
+        };
+        let mut trace = Trace::new(report(path), format);
+        trace.set_funcs(
+            matches
+                .get_many("trace-func")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+        );
+        if let Some(range) = matches.get_one::<Range<usize>>("trace-range") {
+            trace.set_range(range.clone())
+        }
+        trace
+    });
+    let mut cost = matches
+        .get_one::<CostModel>("cost")
+        .map(|model| Cost::new(interpreter.program(), model));
+    let mut reports: Vec<(&str, &String, Box<dyn Report>)> = REPORTS
+        .iter()
+        .filter_map(|&(flag, new)| {
+            let path = matches.get_one::<String>(flag)?;
+            Some((flag, path, new(interpreter.program())))
+        })
+        .collect();
+    let mut hooks: Vec<&mut dyn Hook> = Vec::new();
+    if let Some(trace) = &mut trace {
+        hooks.push(trace)
+    }
+    if let Some(cost) = &mut cost {
+        hooks.push(cost)
+    }
+    for (_, _, hook) in &mut reports {
+        hooks.push(hook.as_mut())
+    }
+    let instr_cnt = match hooks.is_empty() {
+        true => interpreter.exec(),
+        false => interpreter.exec_with(&mut hooks),
+    };
+    for (flag, path, hook) in reports {
+        let text = hook.report(interpreter.program(), format(matches, flag));
+        write!(report(path), "{text}").expect("write error")
+    }
+    if let Some(cost) = cost {
+        eprintln!("cost: {}", cost.total())
+    }
+    instr_cnt
+}
+
+fn run(matches: &ArgMatches) {
+    let nfiles = matches
+        .get_many::<String>("file")
+        .map_or(0, |files| files.len());
+    if matches.contains_id("emit") && nfiles > 1 {
+        // A single listing would merge file-private functions of the same name.
+        panic!("--emit takes a single -f file, found {nfiles}")
+    }
+    let program = load(matches.get_many::<String>("file"));
+    match matches.get_one::<String>("emit").map(String::as_str) {
+        Some("arg") => return print!("{}", program.listing(CallStyle::Arg)),
+        Some("inline") => return print!("{}", program.listing(CallStyle::Inline)),
+        _ => (),
+    }
+    let mut interpreter = Interpreter::new(program, io::stdin(), io::stdout());
+    interpreter.set_implicit_return(implicit_return(matches));
+    for error in interpreter.program().check_args() {
+        eprintln!("warning: {error}")
+    }
+    if let Some(script) = matches.get_one::<String>("debug-script") {
+        let script = fs::File::open(script).expect("file error");
+        let mut debugger = Debugger::new(interpreter);
+        debugger.run_script(io::BufReader::new(script), &mut io::stdout());
+        eprintln!("instrCnt: {}", debugger.instr_cnt());
+        let exit_code = debugger.interpreter().exit_code();
+        drop(debugger);
+        std::process::exit(exit_code)
+    }
+    let locs: Vec<&String> = matches.get_many("break").unwrap_or_default().collect();
+    let targets: Vec<&String> = matches.get_many("watch").unwrap_or_default().collect();
+    let (instr_cnt, exit_code) = if locs.is_empty() && targets.is_empty() {
+        let instr_cnt = exec(&mut interpreter, matches);
+        interpreter.flush();
+        (instr_cnt, interpreter.exit_code())
+    } else {
+        let mut debugger = Debugger::new(interpreter);
+        for loc in locs {
+            if let Err(err) = debugger.add_breakpoint(loc) {
+                panic!("{err}")
+            }
+        }
+        for target in targets {
+            if let Err(err) = debugger.add_watchpoint(target, false) {
+                panic!("{err}")
+            }
+        }
+        debugger.run_batch(&mut io::stderr());
+        for bp in debugger.breakpoints().iter() {
+            eprintln!("Breakpoint {bp}")
+        }
+        for wp in debugger.breakpoints().watches() {
+            eprintln!("Watchpoint {wp}")
+        }
+        (debugger.instr_cnt(), debugger.interpreter().exit_code())
+    };
+    eprintln!("instrCnt: {instr_cnt}");
+    std::process::exit(exit_code)
+}
+
+/// Opens the file given by `--input` for the program to READ from.
+fn input(matches: &ArgMatches) -> Box<dyn Read> {
+    match matches.get_one::<String>("input") {
+        Some(file) => Box::new(fs::File::open(file).expect("file error")),
+        None => Box::new(io::empty()),
+    }
+}
+
+fn debug(matches: &ArgMatches) {
+    let program = load(matches.get_many::<String>("file"));
+    let mut interpreter = Interpreter::new(program, input(matches), io::stdout());
+    interpreter.set_implicit_return(implicit_return(matches));
+    for error in interpreter.program().check_args() {
+        eprintln!("warning: {error}")
+    }
+    let mut debugger = Debugger::new(interpreter);
+    debugger.run(io::stdin().lock(), &mut io::stdout())
+}
+
+fn repl(matches: &ArgMatches) {
+    let mut repl = Repl::new(input(matches), io::stdout());
+    repl.run(io::stdin().lock(), &mut io::stdout())
+}
+
+fn compare_files(matches: &ArgMatches) {
+    let mut input = Vec::new();
+    match matches.get_one::<String>("input") {
+        Some(file) => input = fs::read(file).expect("file error"),
+        None => {
+            io::stdin().read_to_end(&mut input).expect("input error");
+        }
+    }
+    let [a, b] = ["a", "b"].map(|id| {
+        let program = load(matches.get_many::<String>(id));
+        Outcome::run(program, &input, implicit_return(matches))
+    });
+    let names = ["a", "b"].map(|id| matches.get_one::<String>(id).unwrap().as_str());
+    let (report, same) = compare(names, &a, &b);
+    print!("{report}");
+    if !same {
+        std::process::exit(1)
+    }
+}
+
+fn dap() {
+    Server::new(io::stdout()).run(io::stdin().lock())
+}
 
 fn main() {
+    let file =
+        || arg!(-f --file <FILE> "ir file, may be given several times").action(ArgAction::Append);
+    let implicit = || {
+        arg!(--"implicit-return" <MODE> "what running off the end of a function does")
+            .value_parser(["error", "zero"])
+            .default_value("error")
+    };
     let matches = Command::new("misri")
         .version("0.1.0")
         .author("jjppp <jpwang@smail.nju.edu.cn>")
         .about("Yet another interpreter for NJU irsim")
-        .arg(arg!(-f --file <FILE> "ir file"))
+        .args_conflicts_with_subcommands(true)
+        .arg(file())
+        .arg(implicit())
+        .arg(
+            arg!(-b --break <LOC> "dump the state whenever LOC is reached, may be given several times")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(-w --watch <VAR> "log every change of VAR, written as func:var, may be given several times")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"debug-script" <FILE> "run the debugger commands in FILE instead of running freely")
+                .conflicts_with_all(["break", "watch"]),
+        )
+        .arg(
+            arg!(--trace [FILE] "write every instruction executed to FILE, or to stderr")
+                .default_missing_value("-")
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--"trace-format" <FORMAT> "format of the trace")
+                .value_parser(["text", "jsonl"])
+                .default_value("text"),
+        )
+        .arg(
+            arg!(--"trace-func" <FUNC> "only trace instructions of FUNC, may be given several times")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"trace-range" <RANGE> "only trace instructions on source lines START..END")
+                .value_parser(parse_range),
+        )
+        .arg(
+            arg!(--profile [FILE] "write instruction counts per function and instruction to FILE, or to stderr")
+                .default_missing_value("-")
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--"profile-format" <FORMAT> "format of the profile")
+                .value_parser(["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            arg!(--folded [FILE] "write instruction counts per call stack to FILE, or to stderr, for flamegraphs")
+                .default_missing_value("-")
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--coverage [FILE] "write which instructions and branches ran to FILE, or to stderr")
+                .default_missing_value("-")
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--"coverage-format" <FORMAT> "format of the coverage report")
+                .value_parser(["text", "lcov"])
+                .default_value("text"),
+        )
+        .arg(
+            arg!(--branches [FILE] "write how often every IF jumps to FILE, or to stderr")
+                .default_missing_value("-")
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--callgraph [FILE] "write the calls made as a Graphviz graph to FILE, or to stderr")
+                .default_missing_value("-")
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--cost <MODEL> "also report the instructions executed as weighed by MODEL, a file or \"nolabels\"")
+                .value_parser(CostModel::load)
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--stats [FILE] "write stack and memory usage to FILE, or to stderr")
+                .default_missing_value("-")
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--"stats-format" <FORMAT> "format of the statistics")
+                .value_parser(["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            arg!(--ranges [FILE] "write the values every variable took to FILE, or to stderr")
+                .default_missing_value("-")
+                .conflicts_with_all(["break", "watch", "debug-script"]),
+        )
+        .arg(
+            arg!(--"ranges-format" <FORMAT> "format of the value ranges")
+                .value_parser(["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            arg!(--emit <STYLE> "print the program of a single -f file with calls in STYLE instead of running it")
+                .value_parser(["arg", "inline"]),
+        )
+        .subcommand(
+            Command::new("debug")
+                .about("Step through a program interactively")
+                .arg(file())
+                .arg(implicit())
+                .arg(arg!(-i --input <FILE> "file the program READs from")),
+        )
+        .subcommand(
+            Command::new("repl")
+                .about("Run IR instructions as they are typed")
+                .arg(arg!(-i --input <FILE> "file the program READs from")),
+        )
+        .subcommand(
+            Command::new("compare")
+                .about("Run two programs on the same input, and compare their output and instruction counts")
+                .arg(arg!(<a> "ir file run first"))
+                .arg(arg!(<b> "ir file compared with a"))
+                .arg(implicit())
+                .arg(arg!(-i --input <FILE> "file both programs READ from, instead of stdin")),
+        )
+        .subcommand(Command::new("dap").about("Serve the Debug Adapter Protocol over stdio"))
         .get_matches();
 
-    let file = match matches.get_one::<String>("file") {
-        Some(file) => file,
-        None => panic!("arg error"),
-    };
-
-    let cont = fs::read_to_string(file).expect("file error");
-    let mut parser = Parser::from(cont.as_str());
-    let program = parser.parse();
-    let mut interpreter = Interpreter::new(program, io::stdin(), io::stdout());
-    let instr_cnt = interpreter.exec();
-    eprintln!("instrCnt: {instr_cnt}")
+    match matches.subcommand() {
+        Some(("debug", matches)) => debug(matches),
+        Some(("repl", matches)) => repl(matches),
+        Some(("compare", matches)) => compare_files(matches),
+        Some(("dap", _)) => dap(),
+        _ => run(&matches),
+    }
 }
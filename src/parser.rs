@@ -7,49 +7,77 @@ use crate::{
 pub struct Parser {
     lexer: Lexer,
     body: Vec<Instr>,
+    lines: Vec<usize>,
+    /// `ARG`s desugared from the inline argument list of the last call.
+    args: Vec<Instr>,
+    file: String,
 }
 
 impl Parser {
+    #[cfg(test)]
     pub fn from(input: &str) -> Parser {
+        Parser::with_file("<input>", input)
+    }
+
+    /// Creates a parser whose errors and functions refer to `file`.
+    pub fn with_file(file: &str, input: &str) -> Parser {
         Parser {
-            lexer: Lexer::from(String::from(input)),
+            lexer: Lexer::with_file(file, String::from(input)),
             body: Vec::new(),
+            lines: Vec::new(),
+            args: Vec::new(),
+            file: String::from(file),
         }
     }
 
     pub fn parse(&mut self) -> Program {
+        let mut program = self.parse_funcs();
+        program.files.push(self.file.clone());
+        program
+    }
+
+    fn parse_funcs(&mut self) -> Program {
         let (token, lineno) = self.lexer.peek();
         match token {
-            Token::TokFunc => {
+            Token::TokFunc | Token::TokPrivate => {
                 let fun = self.parse_func();
-                let mut program = self.parse();
+                let mut program = self.parse_funcs();
                 program.push_front(fun);
                 program
             }
             Token::TokEOF => Program::new(),
             Token::TokNl => {
                 self.lexer.consume();
-                self.parse()
+                self.parse_funcs()
             }
-            token => panic!("parse error: {:?} at line: {lineno}", token),
+            token => self.error(token, lineno),
         }
     }
 
     fn parse_func(&mut self) -> Func {
+        let private = self.lexer.peek().0 == Token::TokPrivate;
+        if private {
+            self.lexer.consume();
+        }
         self.lexer.consume();
         let name = self.parse_name();
         self.lexer.consume();
         self.lexer.consume();
         self.body = Vec::new();
+        self.lines = Vec::new();
         self.parse_body();
         Func {
-            name,
             body: self.body.clone(),
-            nreg: 0,
-            id: 0,
+            lines: self.lines.clone(),
+            private,
+            ..Func::new(&name)
         }
     }
 
+    fn error(&self, token: Token, lineno: usize) -> ! {
+        panic!("parse error: {:?} at {}:{lineno}", token, self.file)
+    }
+
     fn parse_instr(&mut self) -> Instr {
         let (token, lineno) = self.lexer.peek();
         let instr = match token {
@@ -77,8 +105,9 @@ impl Parser {
                     Token::TokCall => {
                         self.lexer.consume();
                         let name = self.parse_name();
+                        self.parse_call_args();
                         Instr::Call {
-                            x,
+                            x: Some(x),
                             name,
                             id: Default::default(),
                         }
@@ -96,7 +125,7 @@ impl Parser {
                             _ => Instr::Assign(x, y),
                         }
                     }
-                    token => panic!("parse error: {:?} at line: {lineno}", token),
+                    token => self.error(token, lineno),
                 }
             }
             Token::TokStar => {
@@ -127,7 +156,20 @@ impl Parser {
             }
             Token::TokReturn => {
                 self.lexer.consume();
-                Instr::Return(self.parse_operand())
+                match self.lexer.peek().0 {
+                    Token::TokNl | Token::TokEOF => Instr::Return(None),
+                    _ => Instr::Return(Some(self.parse_operand())),
+                }
+            }
+            Token::TokCall => {
+                self.lexer.consume();
+                let name = self.parse_name();
+                self.parse_call_args();
+                Instr::Call {
+                    x: None,
+                    name,
+                    id: Default::default(),
+                }
             }
             Token::TokDec => {
                 self.lexer.consume();
@@ -151,21 +193,69 @@ impl Parser {
                 self.lexer.consume();
                 Instr::Write(self.parse_operand())
             }
-            token => panic!("parse error: {:?} at line: {lineno}", token),
+            Token::TokAssert => {
+                self.lexer.consume();
+                let x = self.parse_operand();
+                let op = self.parse_rel_op();
+                let y = self.parse_operand();
+                let msg = match self.lexer.peek().0 {
+                    Token::TokStr(msg) => {
+                        self.lexer.consume();
+                        Some(msg)
+                    }
+                    _ => None,
+                };
+                Instr::Assert { x, op, y, msg }
+            }
+            token => self.error(token, lineno),
         };
-        match self.lexer.consume().0 {
-            Token::TokNl | Token::TokEOF => (),
-            _ => panic!("parse error: {:?} at line: {lineno}", token),
+        match self.lexer.consume() {
+            (Token::TokNl | Token::TokEOF, _) => (),
+            (token, lineno) => self.error(token, lineno),
         }
         instr
     }
 
+    /// Parses an optional inline argument list `(a, b, #3)`, which desugars to
+    /// `ARG #3`, `ARG b`, `ARG a` before the call, so that the callee's first
+    /// `PARAM` receives the first argument.
+    fn parse_call_args(&mut self) {
+        self.args = Vec::new();
+        if self.lexer.peek().0 != Token::TokLParen {
+            return;
+        }
+        self.lexer.consume();
+        if self.lexer.peek().0 == Token::TokRParen {
+            self.lexer.consume();
+            return;
+        }
+        loop {
+            let arg = self.parse_operand();
+            self.args.push(Instr::Arg(arg));
+            match self.lexer.consume() {
+                (Token::TokComma, _) => (),
+                (Token::TokRParen, _) => break,
+                (token, lineno) => self.error(token, lineno),
+            }
+        }
+        self.args.reverse()
+    }
+
+    /// Parses one line, which is more than one instruction for calls with an
+    /// inline argument list.
+    pub fn parse_line(&mut self) -> Vec<Instr> {
+        let instr = self.parse_instr();
+        let mut instrs = std::mem::take(&mut self.args);
+        instrs.push(instr);
+        instrs
+    }
+
     fn parse_operand(&mut self) -> Operand {
         let (token, lineno) = self.lexer.consume();
         match token {
             Token::TokSharp => Operand::Imm(self.parse_int()),
             Token::TokIden(name) => Operand::from(name),
-            token => panic!("parse error: {:?} at line: {lineno}", token),
+            token => self.error(token, lineno),
         }
     }
 
@@ -178,7 +268,7 @@ impl Parser {
         let (token, lineno) = self.lexer.consume();
         match token {
             Token::TokInt(int) => int * sign,
-            token => panic!("parse error: {:?} at line: {lineno}", token),
+            token => self.error(token, lineno),
         }
     }
 
@@ -191,7 +281,7 @@ impl Parser {
             Token::TokGE => RelOp::GE,
             Token::TokEQ => RelOp::EQ,
             Token::TokNE => RelOp::NE,
-            token => panic!("parse error: {:?} at line: {lineno}", token),
+            token => self.error(token, lineno),
         }
     }
 
@@ -202,7 +292,7 @@ impl Parser {
             Token::TokSub => ArithOp::Sub,
             Token::TokStar => ArithOp::Mul,
             Token::TokDiv => ArithOp::Div,
-            token => panic!("parse error: {:?} at line: {lineno}", token),
+            token => self.error(token, lineno),
         }
     }
 
@@ -210,30 +300,34 @@ impl Parser {
         let (token, lineno) = self.lexer.consume();
         match token {
             Token::TokIden(name) => name,
-            token => panic!("parse error: {:?} at line: {lineno}", token),
+            token => self.error(token, lineno),
         }
     }
 
     fn parse_body(&mut self) {
         let (token, lineno) = self.lexer.peek();
         match token {
-            Token::TokFunc | Token::TokEOF | Token::TokNl => (),
+            Token::TokFunc | Token::TokPrivate | Token::TokEOF | Token::TokNl => (),
             Token::TokIf
             | Token::TokLabel
             | Token::TokIden(_)
             | Token::TokStar
             | Token::TokGoto
             | Token::TokReturn
+            | Token::TokCall
             | Token::TokWrite
             | Token::TokRead
             | Token::TokParam
             | Token::TokDec
-            | Token::TokArg => {
-                let instr = self.parse_instr();
-                self.body.push(instr);
+            | Token::TokArg
+            | Token::TokAssert => {
+                for instr in self.parse_line() {
+                    self.body.push(instr);
+                    self.lines.push(lineno);
+                }
                 self.parse_body()
             }
-            token => panic!("parse error: {:?} at line: {lineno}", token),
+            token => self.error(token, lineno),
         }
     }
 }
@@ -257,12 +351,15 @@ mod tests {
              LABEL wjp :
              IF x < y GOTO wjp
              RETURN x
+             RETURN
              DEC arr 24
              ARG x
              y := CALL foo
+             CALL foo
              PARAM x
              READ x
-             WRITE x",
+             WRITE x
+             ASSERT x >= #0 \"negative\"",
         );
         assert_eq!(
             parser.parse_instr(),
@@ -328,13 +425,25 @@ mod tests {
                 id: Default::default()
             }
         );
-        assert_eq!(parser.parse_instr(), Instr::Return(Operand::from("x")));
+        assert_eq!(
+            parser.parse_instr(),
+            Instr::Return(Some(Operand::from("x")))
+        );
+        assert_eq!(parser.parse_instr(), Instr::Return(None));
         assert_eq!(parser.parse_instr(), Instr::Dec(Operand::from("arr"), 24));
         assert_eq!(parser.parse_instr(), Instr::Arg(Operand::from("x")));
         assert_eq!(
             parser.parse_instr(),
             Instr::Call {
-                x: Operand::from("y"),
+                x: Some(Operand::from("y")),
+                name: String::from("foo"),
+                id: Default::default()
+            }
+        );
+        assert_eq!(
+            parser.parse_instr(),
+            Instr::Call {
+                x: None,
                 name: String::from("foo"),
                 id: Default::default()
             }
@@ -342,6 +451,15 @@ mod tests {
         assert_eq!(parser.parse_instr(), Instr::Param(Operand::from("x")));
         assert_eq!(parser.parse_instr(), Instr::Read(Operand::from("x")));
         assert_eq!(parser.parse_instr(), Instr::Write(Operand::from("x")));
+        assert_eq!(
+            parser.parse_instr(),
+            Instr::Assert {
+                x: Operand::from("x"),
+                op: RelOp::GE,
+                y: Operand::from(0),
+                msg: Some(String::from("negative"))
+            }
+        );
     }
 
     #[test]
@@ -376,7 +494,7 @@ mod tests {
                 },
                 Instr::new_goto("label2"),
                 Instr::Label(String::from("label1")),
-                Instr::Return(Operand::from("v1")),
+                Instr::Return(Some(Operand::from("v1"))),
                 Instr::Label(String::from("label2")),
                 Instr::Arith(
                     Operand::from("t1"),
@@ -386,7 +504,7 @@ mod tests {
                 ),
                 Instr::Arg(Operand::from("t1")),
                 Instr::Call {
-                    x: Operand::from("t2"),
+                    x: Some(Operand::from("t2")),
                     name: String::from("fact"),
                     id: Default::default()
                 },
@@ -396,7 +514,7 @@ mod tests {
                     ArithOp::Mul,
                     Operand::from("t2")
                 ),
-                Instr::Return(Operand::from("t3")),
+                Instr::Return(Some(Operand::from("t3"))),
             ])
         )
     }
@@ -437,4 +555,32 @@ mod tests {
         );
         parser.parse();
     }
+
+    #[test]
+    fn test_call_args() {
+        let mut parser = Parser::from(
+            "FUNCTION main :
+             x := CALL f(a, #-3)
+             CALL g()",
+        );
+        let func = parser.parse_func();
+        assert_eq!(
+            func.body,
+            Vec::from([
+                Instr::Arg(Operand::from(-3)),
+                Instr::Arg(Operand::from("a")),
+                Instr::Call {
+                    x: Some(Operand::from("x")),
+                    name: String::from("f"),
+                    id: Default::default()
+                },
+                Instr::Call {
+                    x: None,
+                    name: String::from("g"),
+                    id: Default::default()
+                },
+            ])
+        );
+        assert_eq!(func.lines, vec![2, 2, 2, 3]);
+    }
 }
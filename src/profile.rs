@@ -0,0 +1,303 @@
+use std::cmp::Reverse;
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    env::Env,
+    exec::Hook,
+    instr::{Func, Instr, Program},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// A table of functions followed by a listing of every instruction.
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FuncProfile {
+    /// Instructions executed in the function itself.
+    exclusive: usize,
+    /// Instructions executed while the function was on the stack.
+    inclusive: usize,
+    calls: usize,
+    /// Times each instruction of the body was executed.
+    counts: Vec<usize>,
+}
+
+/// Counts where the instructions executed go, by function and instruction.
+pub struct Profile {
+    funcs: Vec<FuncProfile>,
+    /// Step at which each function was last counted inclusively, so that a
+    /// recursive function counts once per step.
+    seen: Vec<usize>,
+    step: usize,
+    /// Instruction being executed, between `before` and `after`.
+    pending: Option<(usize, usize)>,
+    /// Functions on the stack while it executes.
+    active: Vec<usize>,
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    match total {
+        0 => 0.0,
+        total => count as f64 * 100.0 / total as f64,
+    }
+}
+
+impl Profile {
+    pub fn new(program: &Program) -> Self {
+        let mut funcs: Vec<FuncProfile> = program
+            .funcs
+            .iter()
+            .map(|func| FuncProfile {
+                counts: vec![0; func.body.len()],
+                ..Default::default()
+            })
+            .collect();
+        funcs[program.entry].calls = 1;
+        Profile {
+            funcs,
+            seen: vec![usize::MAX; program.funcs.len()],
+            step: 0,
+            pending: None,
+            active: Vec::new(),
+        }
+    }
+
+    /// Instructions executed in total.
+    pub fn total(&self) -> usize {
+        self.step
+    }
+
+    /// Counts the instruction at `pc` of `func` in the listing, and the call
+    /// it makes.
+    fn count(&mut self, program: &Program, func: usize, pc: usize) {
+        if let Some(count) = self.funcs[func].counts.get_mut(pc) {
+            *count += 1
+        }
+        if let Some(Instr::Call { id, .. }) = program.funcs[func].body.get(pc) {
+            self.funcs[*id].calls += 1
+        }
+    }
+
+    /// Instructions executed in each function that was called, in program
+    /// order. Intrinsics are left out.
+    pub fn exclusive<'a>(&self, program: &'a Program) -> Vec<(&'a Func, usize)> {
+        program
+            .funcs
+            .iter()
+            .filter(|func| func.builtin.is_none() && self.funcs[func.id].calls > 0)
+            .map(|func| (func, self.funcs[func.id].exclusive))
+            .collect()
+    }
+
+    /// Functions that ran or were called, by exclusive count and then name.
+    fn ranked<'a>(&self, program: &'a Program) -> Vec<(usize, &'a str)> {
+        let mut ranked: Vec<(usize, &str)> = program
+            .funcs
+            .iter()
+            .filter(|func| self.funcs[func.id].calls > 0)
+            .map(|func| (func.id, func.name.as_str()))
+            .collect();
+        ranked.sort_by_key(|&(id, name)| (Reverse(self.funcs[id].exclusive), name));
+        ranked
+    }
+
+    pub fn report(&self, program: &Program, format: ProfileFormat) -> String {
+        match format {
+            ProfileFormat::Text => self.text(program),
+            ProfileFormat::Json => self.json(program).to_string() + "\n",
+        }
+    }
+
+    fn text(&self, program: &Program) -> String {
+        let total = self.total();
+        let mut lines = vec![format!(
+            "{:>10} {:>6} {:>10} {:>6} {:>8}  function",
+            "exclusive", "%", "inclusive", "%", "calls"
+        )];
+        let ranked = self.ranked(program);
+        for &(id, name) in &ranked {
+            let prof = &self.funcs[id];
+            lines.push(format!(
+                "{:>10} {:>5.1}% {:>10} {:>5.1}% {:>8}  {name}",
+                prof.exclusive,
+                percent(prof.exclusive, total),
+                prof.inclusive,
+                percent(prof.inclusive, total),
+                prof.calls,
+            ));
+        }
+        for &(id, _) in &ranked {
+            let func = &program.funcs[id];
+            if func.builtin.is_some() {
+                continue;
+            }
+            lines.push(String::new());
+            lines.push(format!("{} ({})", func.name, program.file(func)));
+            for (pc, instr) in func.body.iter().enumerate() {
+                let count = match self.funcs[id].counts[pc] {
+                    0 => String::from("-"),
+                    count => count.to_string(),
+                };
+                let indent = match instr {
+                    Instr::Label(_) => "",
+                    _ => "  ",
+                };
+                lines.push(format!(
+                    "{count:>10} {:>5}  {indent}{instr}",
+                    func.lines.get(pc).copied().unwrap_or_default()
+                ));
+            }
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn json(&self, program: &Program) -> Json {
+        let funcs: Vec<Json> = self
+            .ranked(program)
+            .into_iter()
+            .map(|(id, name)| {
+                let func = &program.funcs[id];
+                let prof = &self.funcs[id];
+                let instrs: Vec<Json> = func
+                    .body
+                    .iter()
+                    .enumerate()
+                    .map(|(pc, instr)| {
+                        json!({
+                            "pc": pc,
+                            "line": func.lines.get(pc),
+                            "instr": instr.to_string(),
+                            "count": prof.counts[pc],
+                        })
+                    })
+                    .collect();
+                json!({
+                    "name": name,
+                    "file": func.builtin.is_none().then(|| program.file(func)),
+                    "exclusive": prof.exclusive,
+                    "inclusive": prof.inclusive,
+                    "calls": prof.calls,
+                    "instrs": instrs,
+                })
+            })
+            .collect();
+        json!({ "total": self.total(), "functions": funcs })
+    }
+}
+
+impl Hook for Profile {
+    fn before(&mut self, _program: &Program, env: &Env) {
+        let frame = env.top_frame();
+        self.pending = Some((frame.func, frame.pc));
+        self.active.clear();
+        for frame in env.stack() {
+            if self.seen[frame.func] != self.step {
+                self.seen[frame.func] = self.step;
+                self.active.push(frame.func)
+            }
+        }
+    }
+
+    fn after(&mut self, program: &Program, _env: &Env) {
+        let (func, pc) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        self.step += 1;
+        for &func in &self.active {
+            self.funcs[func].inclusive += 1
+        }
+        self.funcs[func].exclusive += 1;
+        self.count(program, func, pc)
+    }
+
+    /// Counts the instruction that stopped the program in the listing only,
+    /// as it is left out of `instrCnt`.
+    fn stop(&mut self, program: &Program, _env: &Env) {
+        if let Some((func, pc)) = self.pending.take() {
+            self.count(program, func, pc)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::tests::run_with;
+
+    use super::*;
+
+    fn profile() -> (Program, Profile, usize) {
+        run_with(
+            "FUNCTION fact :
+PARAM n
+IF n > #1 GOTO rec
+RETURN #1
+LABEL rec :
+m := n - #1
+r := CALL fact(m)
+r := r * n
+RETURN r
+
+FUNCTION main :
+x := CALL fact(#3)
+y := CALL abs(x)
+WRITE y
+RETURN #0
+",
+            Profile::new,
+        )
+    }
+
+    #[test]
+    fn test_text() {
+        let (program, profile, instr_cnt) = profile();
+        assert_eq!(profile.total(), instr_cnt);
+        assert_eq!(
+            profile.text(&program),
+            " exclusive      %  inclusive      %    calls  function
+        19  79.2%         19  79.2%        3  fact
+         5  20.8%         24 100.0%        1  main
+         0   0.0%          0   0.0%        1  abs
+
+fact (a.ir)
+         3     2    PARAM n
+         3     3    IF n > #1 GOTO rec
+         1     4    RETURN #1
+         2     5  LABEL rec :
+         2     6    m := n - #1
+         2     7    ARG m
+         2     7    r := CALL fact
+         2     8    r := r * n
+         2     9    RETURN r
+
+main (a.ir)
+         1    12    ARG #3
+         1    12    x := CALL fact
+         1    13    ARG x
+         1    13    y := CALL abs
+         1    14    WRITE y
+         1    15    RETURN #0
+"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let (program, profile, _) = profile();
+        let json = profile.json(&program);
+        assert_eq!(json["total"], 24);
+        assert_eq!(json["functions"][0]["name"], "fact");
+        assert_eq!(json["functions"][0]["inclusive"], 19);
+        assert_eq!(json["functions"][1]["name"], "main");
+        assert_eq!(json["functions"][1]["calls"], 1);
+        assert_eq!(json["functions"][2]["file"], Json::Null);
+        assert_eq!(
+            json["functions"][0]["instrs"][2],
+            json!({ "pc": 2, "line": 4, "instr": "RETURN #1", "count": 1 })
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    env::Env,
+    exec::Hook,
+    instr::{Instr, Operand, Program},
+    value::Value,
+};
+
+/// Distinct values counted per variable before only noting that there are
+/// more.
+const CAP: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangesFormat {
+    Text,
+    Json,
+}
+
+/// Values a variable took.
+#[derive(Debug, Clone, Default)]
+struct Range {
+    /// Smallest and largest integer, if it ever held one.
+    bounds: Option<(i64, i64)>,
+    /// Distinct integers, up to `CAP` of them.
+    distinct: HashSet<i64>,
+    /// Whether there were more than `CAP` distinct integers.
+    more: bool,
+    pointer: bool,
+}
+
+impl Range {
+    fn record(&mut self, value: &Value) {
+        let int = match value {
+            Value::ValInt(int) => *int as i32 as i64,
+            Value::ValPtr { .. } => {
+                self.pointer = true;
+                return;
+            }
+        };
+        self.bounds = Some(match self.bounds {
+            Some((min, max)) => (min.min(int), max.max(int)),
+            None => (int, int),
+        });
+        if self.distinct.len() < CAP {
+            self.distinct.insert(int);
+        } else if !self.distinct.contains(&int) {
+            self.more = true
+        }
+    }
+
+    fn distinct(&self) -> String {
+        match self.more {
+            true => format!("{CAP}+"),
+            false => self.distinct.len().to_string(),
+        }
+    }
+}
+
+/// Records the values every variable is set to.
+pub struct Ranges {
+    /// Ranges by function and variable id.
+    vars: BTreeMap<(usize, usize), Range>,
+    /// Depth, function and pc of the instruction being executed.
+    pending: Option<(usize, usize, usize)>,
+}
+
+impl Ranges {
+    pub fn new() -> Self {
+        Ranges {
+            vars: BTreeMap::new(),
+            pending: None,
+        }
+    }
+
+    fn record(&mut self, func: usize, x: &Operand, env: &Env) {
+        if let Operand::Reg { id, .. } = x {
+            let range = self.vars.entry((func, *id)).or_default();
+            range.record(&env.get(x))
+        }
+    }
+
+    pub fn report(&self, program: &Program, format: RangesFormat) -> String {
+        match format {
+            RangesFormat::Text => self.text(program),
+            RangesFormat::Json => self.json(program).to_string() + "\n",
+        }
+    }
+
+    /// Variables that were set, with their functions and names.
+    fn ranges<'a>(
+        &'a self,
+        program: &'a Program,
+    ) -> impl Iterator<Item = (usize, &'a str, &'a Range)> {
+        self.vars.iter().map(|(&(func, id), range)| {
+            let name = program.funcs[func].binding.names()[id].as_str();
+            (func, name, range)
+        })
+    }
+
+    fn text(&self, program: &Program) -> String {
+        let mut lines = Vec::new();
+        let mut last = None;
+        for (func, name, range) in self.ranges(program) {
+            if last != Some(func) {
+                let func = &program.funcs[func];
+                if last.is_some() {
+                    lines.push(String::new())
+                }
+                lines.push(format!("{} ({})", func.name, program.file(func)));
+                lines.push(format!(
+                    "    {:<12} {:>11} {:>11} {:>8}",
+                    "variable", "min", "max", "distinct"
+                ));
+                last = Some(func.id);
+            }
+            let (min, max) = match range.bounds {
+                Some((min, max)) => (min.to_string(), max.to_string()),
+                None => (String::from("-"), String::from("-")),
+            };
+            let mut line = format!("    {name:<12} {min:>11} {max:>11} {:>8}", range.distinct());
+            let notes: Vec<&str> = [
+                (range.distinct.len() == 1 && !range.pointer, "constant"),
+                (range.pointer, "pointer"),
+            ]
+            .into_iter()
+            .filter_map(|(note, text)| note.then_some(text))
+            .collect();
+            if !notes.is_empty() {
+                line += &format!("  {}", notes.join(", "));
+            }
+            lines.push(line);
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn json(&self, program: &Program) -> Json {
+        let vars: Vec<Json> = self
+            .ranges(program)
+            .map(|(func, name, range)| {
+                json!({
+                    "func": program.funcs[func].name,
+                    "name": name,
+                    "min": range.bounds.map(|(min, _)| min),
+                    "max": range.bounds.map(|(_, max)| max),
+                    "distinct": range.distinct.len(),
+                    "more": range.more,
+                    "pointer": range.pointer,
+                })
+            })
+            .collect();
+        json!({ "cap": CAP, "variables": vars })
+    }
+}
+
+impl Hook for Ranges {
+    fn before(&mut self, _program: &Program, env: &Env) {
+        let frame = env.top_frame();
+        self.pending = Some((env.stack().len(), frame.func, frame.pc))
+    }
+
+    fn after(&mut self, program: &Program, env: &Env) {
+        let (depth, func, pc) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let frame = env.top_frame();
+        if env.stack().len() == depth && frame.func == func {
+            if let Some(x) = program.funcs[func].body.get(pc).and_then(Instr::dest) {
+                self.record(func, x, env)
+            }
+        } else if env.stack().len() < depth {
+            // A return sets the result of the call it returns to.
+            let call = program.funcs[frame.func].body.get(frame.pc.wrapping_sub(1));
+            if let Some(x @ Operand::Reg { .. }) = call.and_then(Instr::dest) {
+                self.record(frame.func, x, env)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::tests::run_with;
+
+    use super::*;
+
+    fn ranges(format: RangesFormat) -> String {
+        let (program, ranges, _) = run_with(
+            "FUNCTION double :
+PARAM n
+r := n * #2
+RETURN r
+
+FUNCTION main :
+DEC arr 80
+p := &arr
+k := #4
+i := #0
+LABEL loop :
+IF i >= #20 GOTO end
+d := CALL double(i)
+i := i + #1
+p := p + k
+GOTO loop
+LABEL end :
+m := #0 - i
+RETURN #0
+",
+            |_| Ranges::new(),
+        );
+        ranges.report(&program, format)
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            ranges(RangesFormat::Text),
+            "double (a.ir)
+    variable             min         max distinct
+    n                      0          19      16+
+    r                      0          38      16+
+
+main (a.ir)
+    variable             min         max distinct
+    arr                    -           -        0  pointer
+    p                      -           -        0  pointer
+    k                      4           4        1  constant
+    i                      0          20      16+
+    d                      0          38      16+
+    m                    -20         -20        1  constant
+"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let json: Json = serde_json::from_str(&ranges(RangesFormat::Json)).unwrap();
+        assert_eq!(json["cap"], 16);
+        assert_eq!(
+            json["variables"][4],
+            json!({
+                "func": "main", "name": "k", "min": 4, "max": 4,
+                "distinct": 1, "more": false, "pointer": false,
+            })
+        );
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    fmt::Debug,
+    io::{BufRead, Read, Write},
+};
+
+use crate::{
+    debug::{catch, show},
+    env::Effect,
+    exec::Interpreter,
+    instr::{Func, Instr, Program},
+    parser::Parser,
+    value::Value,
+};
+
+/// File that errors and functions typed into the REPL refer to.
+const FILE: &str = "<repl>";
+
+/// Formats a variable, followed by the cells of the `DEC` object it points
+/// into if it is a pointer.
+fn describe(name: &str, value: &Value) -> String {
+    match value {
+        Value::ValPtr { mem, size, .. } => {
+            let cells: Vec<String> = mem.borrow()[..size.div_ceil(4)]
+                .iter()
+                .map(|int| (*int as i32).to_string())
+                .collect();
+            format!("{name} = {} [{}]", show(value), cells.join(", "))
+        }
+        Value::ValInt(_) => format!("{name} = {}", show(value)),
+    }
+}
+
+/// Runs IR typed one line at a time. Instructions run at the top level as
+/// soon as they are entered, while `FUNCTION`s are kept to be called later.
+pub struct Repl<T, U>
+where
+    U: Write,
+{
+    /// Runs the top level as the body of `main`, which grows with every line.
+    interpreter: Interpreter<T, U>,
+    /// Lines of the functions being typed, up to a blank line.
+    defining: Option<Vec<String>>,
+    /// Number of top-level lines entered, used as their line numbers.
+    lineno: usize,
+}
+
+impl<T, U> Repl<T, U>
+where
+    T: Read,
+    U: Write + Debug,
+{
+    /// Creates a REPL whose program READs from `fin` and WRITEs to `fout`.
+    pub fn new(fin: T, fout: U) -> Self {
+        let mut program = Program::new();
+        program.funcs.push_back(Func::new("main"));
+        program.files.push(String::from(FILE));
+        let mut interpreter = Interpreter::new(program, fin, fout);
+        interpreter.env_mut().record(true);
+        Repl {
+            interpreter,
+            defining: None,
+            lineno: 0,
+        }
+    }
+
+    /// Adds the functions defined in `text`, replacing any of the same name.
+    pub fn define(&mut self, text: &str) -> Result<(), String> {
+        let saved = self.interpreter.program().clone();
+        let program = self.interpreter.program_mut();
+        let result = catch(|| {
+            for func in Parser::with_file(FILE, text).parse().funcs {
+                if func.name == "main" {
+                    panic!("main is the top level of the REPL and cannot be defined")
+                }
+                match program.funcs.iter().position(|old| old.name == func.name) {
+                    Some(id) => program.funcs[id] = func,
+                    None => program.funcs.push_back(func),
+                }
+            }
+            program.init()
+        });
+        if result.is_err() {
+            *program = saved
+        }
+        result
+    }
+
+    /// Runs one line of instructions at the top level, returning the
+    /// variables it changed. On an error, everything but output is reverted.
+    pub fn exec(&mut self, line: &str) -> Result<Vec<String>, String> {
+        self.lineno += 1;
+        let saved = self.interpreter.program().clone();
+        let entry = saved.entry;
+        let lineno = self.lineno;
+        let interpreter = &mut self.interpreter;
+        let result = catch(|| {
+            let instrs = Parser::with_file(FILE, line).parse_line();
+            if let Some(instr) = instrs.iter().find(|instr| {
+                matches!(
+                    instr,
+                    Instr::Label(_)
+                        | Instr::Goto { .. }
+                        | Instr::Cond { .. }
+                        | Instr::Return(_)
+                        | Instr::Param(_)
+                )
+            }) {
+                panic!("{instr} can only be used inside a FUNCTION")
+            }
+            let program = interpreter.program_mut();
+            let main = &mut program.funcs[entry];
+            main.lines.extend(instrs.iter().map(|_| lineno));
+            main.body.extend(instrs);
+            program.init();
+            let main = &program.funcs[entry];
+            let (nreg, end) = (main.nreg, main.body.len());
+            interpreter.env_mut().top_frame_mut().grow(nreg);
+            while interpreter.env().stack().len() > 1 || interpreter.env().pc() < end {
+                if !interpreter.tick() {
+                    break;
+                }
+            }
+            interpreter.flush()
+        });
+        let effects = interpreter.env_mut().effects();
+        if let Err(err) = result {
+            interpreter.undo(effects);
+            *interpreter.program_mut() = saved;
+            return Err(err);
+        }
+
+        let program = interpreter.program();
+        let frame = &interpreter.env().stack()[0];
+        let vars = program.vars(frame);
+        let mut changed: Vec<&str> = Vec::new();
+        for effect in effects {
+            let name = match effect {
+                Effect::Set { depth: 0, id, .. } => {
+                    program.funcs[entry].binding.names()[id].as_str()
+                }
+                // Memory is shown through the first variable pointing into it.
+                Effect::Store { ptr, .. } => {
+                    match vars.iter().find(|(_, value)| value.same_object(&ptr)) {
+                        Some((name, _)) => name,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            if !changed.contains(&name) {
+                changed.push(name)
+            }
+        }
+        Ok(vars
+            .iter()
+            .filter(|(name, _)| changed.contains(name))
+            .map(|(name, value)| describe(name, value))
+            .collect())
+    }
+
+    /// Variables of the top level with their values.
+    fn vars(&self) -> Vec<String> {
+        let program = self.interpreter.program();
+        let frame = &self.interpreter.env().stack()[0];
+        program
+            .vars(frame)
+            .into_iter()
+            .map(|(name, value)| describe(name, value))
+            .collect()
+    }
+
+    /// Prompt for the next line.
+    fn prompt(&self) -> &'static str {
+        match self.defining {
+            Some(_) => "... ",
+            None => "misri> ",
+        }
+    }
+
+    /// Handles one line typed into the REPL. Returns `false` when the session
+    /// should end, either by `quit` or because the program stopped.
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> bool {
+        let lines = match (&mut self.defining, line.split_whitespace().next()) {
+            (Some(lines), Some(_)) => {
+                lines.push(String::from(line));
+                Ok(Vec::new())
+            }
+            (Some(_), None) => {
+                let text = self.defining.take().unwrap().join("\n");
+                self.define(&text).map(|_| Vec::new())
+            }
+            (None, None) => Ok(Vec::new()),
+            (None, Some("quit")) if line.trim() == "quit" => return false,
+            (None, Some("vars")) if line.trim() == "vars" => Ok(self.vars()),
+            (None, Some("FUNCTION" | "PRIVATE")) => {
+                self.defining = Some(vec![String::from(line)]);
+                Ok(Vec::new())
+            }
+            (None, Some(_)) => self.exec(line),
+        };
+        let lines = lines.unwrap_or_else(|err| vec![format!("error: {err}")]);
+        for line in lines {
+            writeln!(out, "{line}").expect("write error");
+        }
+        if self.interpreter.done() {
+            writeln!(out, "program exited").expect("write error");
+            return false;
+        }
+        true
+    }
+
+    /// Reads lines from `input` until it ends or `quit` is given. A function
+    /// still being typed when the input ends is defined as well.
+    pub fn run(&mut self, input: impl BufRead, out: &mut impl Write) {
+        let mut lines = input.lines();
+        loop {
+            write!(out, "{}", self.prompt()).expect("write error");
+            out.flush().expect("write error");
+            let line = match lines.next() {
+                Some(line) => line.expect("input error"),
+                None => break,
+            };
+            if !self.command(&line, out) {
+                return;
+            }
+        }
+        if self.defining.is_some() {
+            self.command("", out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(input: &str, lines: &str) -> String {
+        let mut repl = Repl::new(input.as_bytes(), Vec::new());
+        let out = &mut Vec::new();
+        for line in lines.lines() {
+            if !repl.command(line.trim(), out) {
+                break;
+            }
+        }
+        String::from_utf8(out.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_instrs() {
+        assert_eq!(
+            session(
+                "42\n",
+                "x := #3
+                 DEC a 8
+                 p := a + #4
+                 *p := x
+                 y := *p
+                 READ z
+                 vars"
+            ),
+            "x = 3
+a = ptr+0 (8 bytes) [0, 0]
+p = ptr+4 (8 bytes) [0, 0]
+a = ptr+0 (8 bytes) [0, 3]
+y = 3
+z = 42
+x = 3
+a = ptr+0 (8 bytes) [0, 3]
+p = ptr+4 (8 bytes) [0, 3]
+y = 3
+z = 42
+"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(
+            session(
+                "",
+                "FUNCTION sq :
+                 PARAM n
+                 r := n * n
+                 RETURN r
+
+                 x := CALL sq(#4)
+                 y := CALL abs(#-3)
+                 FUNCTION sq :
+                 PARAM n
+                 RETURN n
+
+                 FUNCTION abs :
+                 PARAM n
+                 RETURN #7
+
+                 x := CALL sq(#4)
+                 y := CALL abs(#-3)
+                 CALL exit(#0)
+                 x := #1"
+            ),
+            "x = 16
+y = 3
+x = 4
+y = 7
+program exited
+"
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!(
+            session(
+                "",
+                "DEC a 4
+                 FUNCTION set :
+                 PARAM p
+                 *p := #7
+                 ASSERT #0 == #1
+                 RETURN #0
+
+                 CALL set(a)
+                 x := CALL f
+                 GOTO l
+                 x := y +
+                 FUNCTION main :
+                 RETURN #0
+
+                 vars"
+            ),
+            "a = ptr+0 (4 bytes) [0]
+error: assertion #0 == #1 failed with 0 == 1 at set (<repl>:4)
+error: undefined function f called in <repl>
+error: GOTO l can only be used inside a FUNCTION
+error: parse error: TokEOF at <repl>:1
+error: main is the top level of the REPL and cannot be defined
+a = ptr+0 (4 bytes) [0]
+"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    env::Env,
+    exec::Hook,
+    instr::{Instr, Program},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+}
+
+/// Records how much stack and memory the program used.
+pub struct Stats {
+    peak_depth: usize,
+    /// Most arguments pushed by `ARG` and not yet taken by a call.
+    peak_args: usize,
+    total_bytes: usize,
+    live_bytes: usize,
+    peak_bytes: usize,
+    /// Bytes allocated by `DEC` in every frame on the stack.
+    frame_bytes: Vec<usize>,
+    /// Number of allocations made by each `DEC`, by function and pc.
+    allocs: BTreeMap<(usize, usize), usize>,
+    /// Functions that had a frame pushed.
+    ran: Vec<bool>,
+    /// Site and size of the `DEC` being executed.
+    pending: Option<((usize, usize), usize)>,
+}
+
+impl Stats {
+    pub fn new(program: &Program) -> Self {
+        let mut ran = vec![false; program.funcs.len()];
+        ran[program.entry] = true;
+        Stats {
+            peak_depth: 1,
+            peak_args: 0,
+            total_bytes: 0,
+            live_bytes: 0,
+            peak_bytes: 0,
+            frame_bytes: vec![0],
+            allocs: BTreeMap::new(),
+            ran,
+            pending: None,
+        }
+    }
+
+    /// The function with the most variables among those that ran.
+    fn largest_frame<'a>(&self, program: &'a Program) -> Option<(&'a str, usize)> {
+        program
+            .funcs
+            .iter()
+            .filter(|func| self.ran[func.id])
+            .min_by_key(|func| (Reverse(func.nreg), func.id))
+            .map(|func| (func.name.as_str(), func.nreg))
+    }
+
+    pub fn report(&self, program: &Program, format: StatsFormat) -> String {
+        match format {
+            StatsFormat::Text => self.text(program),
+            StatsFormat::Json => self.json(program).to_string() + "\n",
+        }
+    }
+
+    fn text(&self, program: &Program) -> String {
+        let mut lines = vec![
+            format!("peak call depth: {}", self.peak_depth),
+            format!("peak pending arguments: {}", self.peak_args),
+            format!(
+                "DEC bytes: {} total, {} peak live",
+                self.total_bytes, self.peak_bytes
+            ),
+        ];
+        if let Some((name, nreg)) = self.largest_frame(program) {
+            lines.push(format!("largest frame: {name}, {nreg} variables"));
+        }
+        if !self.allocs.is_empty() {
+            lines.push(String::from("DEC sites:"));
+        }
+        for (&(func, pc), allocs) in &self.allocs {
+            let instr = &program.funcs[func].body[pc];
+            let allocs = match allocs {
+                1 => String::from("1 allocation"),
+                allocs => format!("{allocs} allocations"),
+            };
+            lines.push(format!(
+                "    {}  {instr}  {allocs}",
+                program.loc_at(func, pc)
+            ));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn json(&self, program: &Program) -> Json {
+        let sites: Vec<Json> = self
+            .allocs
+            .iter()
+            .map(|(&(func, pc), allocs)| {
+                let func = &program.funcs[func];
+                let size = match func.body[pc] {
+                    Instr::Dec(_, size) => size,
+                    _ => 0,
+                };
+                json!({
+                    "func": func.name,
+                    "pc": pc,
+                    "line": func.lines.get(pc),
+                    "size": size,
+                    "allocations": allocs,
+                })
+            })
+            .collect();
+        json!({
+            "peak_depth": self.peak_depth,
+            "peak_args": self.peak_args,
+            "dec_bytes_total": self.total_bytes,
+            "dec_bytes_peak": self.peak_bytes,
+            "largest_frame": self.largest_frame(program).map(|(name, nreg)| {
+                json!({ "func": name, "nreg": nreg })
+            }),
+            "dec_sites": sites,
+        })
+    }
+}
+
+impl Hook for Stats {
+    fn before(&mut self, program: &Program, env: &Env) {
+        let frame = env.top_frame();
+        if let Some(Instr::Dec(_, size)) = program.funcs[frame.func].body.get(frame.pc) {
+            self.pending = Some(((frame.func, frame.pc), *size as usize))
+        }
+    }
+
+    fn after(&mut self, _program: &Program, env: &Env) {
+        let depth = env.stack().len();
+        self.peak_depth = self.peak_depth.max(depth);
+        self.peak_args = self.peak_args.max(env.nargs());
+        // Memory allocated by a frame lives until it returns.
+        while self.frame_bytes.len() > depth {
+            self.live_bytes -= self.frame_bytes.pop().unwrap_or_default()
+        }
+        if self.frame_bytes.len() < depth {
+            self.frame_bytes.push(0);
+            self.ran[env.top_frame().func] = true
+        }
+        if let Some((site, size)) = self.pending.take() {
+            *self.allocs.entry(site).or_default() += 1;
+            self.total_bytes += size;
+            self.live_bytes += size;
+            self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+            if let Some(bytes) = self.frame_bytes.last_mut() {
+                *bytes += size
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::tests::run_with;
+
+    use super::*;
+
+    fn stats() -> (Program, Stats) {
+        let (program, stats, _) = run_with(
+            "FUNCTION fill :
+PARAM n
+DEC buf 16
+IF n <= #0 GOTO end
+m := n - #1
+CALL fill(m)
+LABEL end :
+RETURN #0
+
+FUNCTION main :
+DEC arr 8
+CALL fill(#2)
+CALL max(#1, #2)
+DEC big 100
+RETURN #0
+",
+            Stats::new,
+        );
+        (program, stats)
+    }
+
+    #[test]
+    fn test_text() {
+        let (program, stats) = stats();
+        assert_eq!(
+            stats.report(&program, StatsFormat::Text),
+            "peak call depth: 4
+peak pending arguments: 2
+DEC bytes: 156 total, 108 peak live
+largest frame: fill, 3 variables
+DEC sites:
+    fill (a.ir:3)  DEC buf 16  3 allocations
+    main (a.ir:11)  DEC arr 8  1 allocation
+    main (a.ir:14)  DEC big 100  1 allocation
+"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let (program, stats) = stats();
+        let json = stats.json(&program);
+        assert_eq!(json["dec_bytes_peak"], 108);
+        assert_eq!(json["largest_frame"], json!({ "func": "fill", "nreg": 3 }));
+        assert_eq!(
+            json["dec_sites"][0],
+            json!({ "func": "fill", "pc": 1, "line": 3, "size": 16, "allocations": 3 })
+        );
+    }
+}
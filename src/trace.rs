@@ -0,0 +1,359 @@
+use std::{io::Write, ops::Range};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    debug::show,
+    env::Env,
+    exec::Hook,
+    instr::{Instr, Operand, Program},
+    value::Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One line per step, for reading.
+    Text,
+    /// One JSON object per line and step, for tools.
+    Jsonl,
+}
+
+/// Parses `START..END`, where either end may be left out and `END` is
+/// excluded.
+pub fn parse_range(range: &str) -> Result<Range<usize>, String> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| format!("expected START..END, found {range}"))?;
+    let bound = |bound: &str, default| match bound {
+        "" => Ok(default),
+        bound => bound
+            .parse()
+            .map_err(|_| format!("invalid number: {bound}")),
+    };
+    Ok(bound(start, 0)?..bound(end, usize::MAX)?)
+}
+
+fn to_json(value: &Value) -> Json {
+    match value {
+        Value::ValInt(int) => json!(*int as i32),
+        Value::ValPtr { size, ptr, .. } => json!({ "offset": *ptr as i64, "size": size }),
+    }
+}
+
+/// A step being traced, between `before` and `after`. It is described up
+/// front, so that a step that fails can still be written.
+struct Event {
+    step: usize,
+    depth: usize,
+    func: usize,
+    pc: usize,
+    name: String,
+    loc: String,
+    line: Option<usize>,
+    instr: String,
+    reads: Vec<(String, Value)>,
+}
+
+/// Writes every instruction executed, with the values it reads and writes.
+pub struct Trace<W: Write> {
+    out: W,
+    format: TraceFormat,
+    /// Functions to trace, or all if empty.
+    funcs: Vec<String>,
+    /// Source lines to trace, or all if `None`.
+    range: Option<Range<usize>>,
+    step: usize,
+    event: Option<Event>,
+}
+
+impl<W: Write> Trace<W> {
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        Trace {
+            out,
+            format,
+            funcs: Vec::new(),
+            range: None,
+            step: 0,
+            event: None,
+        }
+    }
+
+    /// Only traces instructions of the functions in `funcs`.
+    pub fn set_funcs(&mut self, funcs: Vec<String>) {
+        self.funcs = funcs
+    }
+
+    /// Only traces instructions on the source lines in `range`.
+    pub fn set_range(&mut self, range: Range<usize>) {
+        self.range = Some(range)
+    }
+
+    /// Finds the variable `instr` wrote, as seen after executing it.
+    fn write(instr: &Instr, env: &Env) -> Option<(String, Value)> {
+        match instr {
+            Instr::Store(x, _) => {
+                let cell = env.get(x).peek()?;
+                Some((format!("*{x}"), Value::new_int(cell)))
+            }
+            instr => {
+                let x @ Operand::Reg { .. } = instr.dest()? else {
+                    return None;
+                };
+                Some((x.to_string(), env.get(x)))
+            }
+        }
+    }
+
+    fn text(&self, event: &Event, write: Option<(String, Value)>) -> String {
+        let indent = "  ".repeat(event.depth - 1);
+        let mut line = format!("{:>8} {indent}{}  {}", event.step, event.loc, event.instr);
+        if !event.reads.is_empty() {
+            let reads: Vec<String> = event
+                .reads
+                .iter()
+                .map(|(name, value)| format!("{name} = {}", show(value)))
+                .collect();
+            line += &format!("  [{}]", reads.join(", "));
+        }
+        if let Some((name, value)) = write {
+            line += &format!(" -> {name} = {}", show(&value));
+        }
+        line
+    }
+
+    fn jsonl(&self, event: &Event, write: Option<(String, Value)>) -> String {
+        let reads: Vec<Json> = event
+            .reads
+            .iter()
+            .map(|(name, value)| json!({ "name": name, "value": to_json(value) }))
+            .collect();
+        let mut line = json!({
+            "step": event.step,
+            "depth": event.depth,
+            "func": event.name,
+            "pc": event.pc,
+            "line": event.line,
+            "instr": event.instr,
+            "reads": reads,
+        });
+        if let Some((name, value)) = write {
+            line["write"] = json!({ "name": name, "value": to_json(&value) });
+        }
+        line.to_string()
+    }
+
+    fn line(&self, event: &Event, write: Option<(String, Value)>) -> String {
+        match self.format {
+            TraceFormat::Text => self.text(event, write),
+            TraceFormat::Jsonl => self.jsonl(event, write),
+        }
+    }
+}
+
+impl<W: Write> Hook for Trace<W> {
+    fn before(&mut self, program: &Program, env: &Env) {
+        self.step += 1;
+        let frame = env.top_frame();
+        let func = &program.funcs[frame.func];
+        let line = func.lines.get(frame.pc).copied();
+        let in_range = match (&self.range, line) {
+            (None, _) => true,
+            (Some(range), Some(line)) => range.contains(&line),
+            (Some(_), None) => false,
+        };
+        if !in_range || !(self.funcs.is_empty() || self.funcs.contains(&func.name)) {
+            return;
+        }
+        let instr = func.body.get(frame.pc);
+        let reads = match instr {
+            Some(instr) => instr
+                .reads()
+                .into_iter()
+                .map(|x| (x.to_string(), env.get(x)))
+                .collect(),
+            None => Vec::new(),
+        };
+        self.event = Some(Event {
+            step: self.step,
+            depth: env.stack().len(),
+            func: frame.func,
+            pc: frame.pc,
+            name: func.name.clone(),
+            loc: program.loc_at(frame.func, frame.pc),
+            line,
+            instr: instr.map_or(String::from("<end of function>"), |instr| instr.to_string()),
+            reads,
+        })
+    }
+
+    fn after(&mut self, program: &Program, env: &Env) {
+        let event = match self.event.take() {
+            Some(event) => event,
+            None => return,
+        };
+        let frame = env.top_frame();
+        let write = if env.stack().len() == event.depth && frame.func == event.func {
+            program.funcs[event.func]
+                .body
+                .get(event.pc)
+                .and_then(|instr| Self::write(instr, env))
+        } else if env.stack().len() < event.depth {
+            // A return sets the result of the call it returns to.
+            let call = program.funcs[frame.func].body.get(frame.pc.wrapping_sub(1));
+            call.and_then(|call| Self::write(call, env))
+        } else {
+            // A call writes its result only when the callee returns.
+            None
+        };
+        let line = self.line(&event, write);
+        writeln!(self.out, "{line}").expect("write error")
+    }
+
+    fn stop(&mut self, program: &Program, env: &Env) {
+        self.after(program, env)
+    }
+}
+
+impl<W: Write> Drop for Trace<W> {
+    /// Writes the step that was under way when the program failed, which
+    /// never reached `after`.
+    fn drop(&mut self) {
+        if let Some(event) = self.event.take() {
+            let line = self.line(&event, None);
+            let _ = writeln!(self.out, "{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{debug::catch, exec::tests::run_with};
+
+    use super::*;
+
+    const CODE: &str = "FUNCTION inc :
+PARAM n
+r := n + #1
+RETURN r
+
+FUNCTION main :
+DEC arr 8
+p := &arr
+*p := #5
+x := *p
+y := CALL inc(x)
+WRITE y
+RETURN #0
+";
+
+    fn trace(format: TraceFormat, funcs: &[&str], range: &str) -> String {
+        let mut out = Vec::new();
+        run_with(CODE, |_| {
+            let mut trace = Trace::new(&mut out, format);
+            trace.set_funcs(funcs.iter().map(|func| String::from(*func)).collect());
+            trace.set_range(parse_range(range).unwrap());
+            trace
+        });
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            trace(TraceFormat::Text, &[], ".."),
+            "       1 main (a.ir:7)  DEC arr 8 -> arr = ptr+0 (8 bytes)
+       2 main (a.ir:8)  p := &arr  [arr = ptr+0 (8 bytes)] -> p = ptr+0 (8 bytes)
+       3 main (a.ir:9)  *p := #5  [p = ptr+0 (8 bytes)] -> *p = 5
+       4 main (a.ir:10)  x := *p  [p = ptr+0 (8 bytes)] -> x = 5
+       5 main (a.ir:11)  ARG x  [x = 5]
+       6 main (a.ir:11)  y := CALL inc
+       7   inc (a.ir:2)  PARAM n -> n = 5
+       8   inc (a.ir:3)  r := n + #1  [n = 5] -> r = 6
+       9   inc (a.ir:4)  RETURN r  [r = 6] -> y = 6
+      10 main (a.ir:12)  WRITE y  [y = 6]
+      11 main (a.ir:13)  RETURN #0
+"
+        );
+        assert_eq!(
+            trace(TraceFormat::Text, &["inc"], "3.."),
+            "       8   inc (a.ir:3)  r := n + #1  [n = 5] -> r = 6
+       9   inc (a.ir:4)  RETURN r  [r = 6] -> y = 6
+"
+        );
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let out = trace(TraceFormat::Jsonl, &["main"], "9..11");
+        let lines: Vec<Json> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                json!({
+                    "step": 3, "depth": 1, "func": "main", "pc": 2, "line": 9, "instr": "*p := #5",
+                    "reads": [{ "name": "p", "value": { "offset": 0, "size": 8 } }],
+                    "write": { "name": "*p", "value": 5 },
+                }),
+                json!({
+                    "step": 4, "depth": 1, "func": "main", "pc": 3, "line": 10, "instr": "x := *p",
+                    "reads": [{ "name": "p", "value": { "offset": 0, "size": 8 } }],
+                    "write": { "name": "x", "value": 5 },
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeated_reads() {
+        let mut out = Vec::new();
+        run_with(
+            "FUNCTION main :
+n := #3
+r := n * n
+RETURN #0
+",
+            |_| {
+                let mut trace = Trace::new(&mut out, TraceFormat::Jsonl);
+                trace.set_range(3..4);
+                trace
+            },
+        );
+        let line: Json = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            line["reads"],
+            json!([{ "name": "n", "value": 3 }, { "name": "n", "value": 3 }])
+        );
+    }
+
+    #[test]
+    fn test_failed() {
+        let mut out = Vec::new();
+        let code = "FUNCTION main :
+n := #3
+r := n / #0
+RETURN #0
+";
+        assert!(catch(|| run_with(code, |_| Trace::new(&mut out, TraceFormat::Text))).is_err());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "       1 main (a.ir:2)  n := #3 -> n = 3
+       2 main (a.ir:3)  r := n / #0  [n = 3]
+"
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("10..20"), Ok(10..20));
+        assert_eq!(parse_range("..20"), Ok(0..20));
+        assert_eq!(parse_range("10.."), Ok(10..usize::MAX));
+        assert_eq!(
+            parse_range("10"),
+            Err(String::from("expected START..END, found 10"))
+        );
+        assert_eq!(parse_range("a..2"), Err(String::from("invalid number: a")));
+    }
+}
@@ -23,6 +23,15 @@ impl Value {
         }
     }
 
+    /// Reads the cell a pointer points to, or `None` if it is outside of the
+    /// bytes allocated by `DEC`.
+    pub fn peek(&self) -> Option<i64> {
+        match self {
+            Value::ValPtr { mem, size, ptr } if *ptr < *size => Some(mem.borrow()[*ptr / 4]),
+            _ => None,
+        }
+    }
+
     pub fn load(&self) -> Value {
         match self {
             Value::ValPtr { mem, size, ptr } => {
@@ -36,20 +45,46 @@ impl Value {
         }
     }
 
-    pub fn store(&self, val: Value) {
+    /// Stores `val` to the cell a pointer points to, returning the old content.
+    pub fn store(&self, val: Value) -> i64 {
         match self {
             Value::ValPtr { mem, size, ptr } => {
                 let idx = *ptr / 4;
                 if idx >= *size {
                     panic!("store out of buond");
                 }
+                let old = mem.borrow()[idx];
                 if let Value::ValInt(int) = val {
                     mem.borrow_mut()[idx] = int
                 }
+                old
             }
             Value::ValInt(_) => panic!("cannot store ValInt!"),
         }
     }
+
+    /// Whether two pointers point into the same allocation.
+    pub fn same_object(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::ValPtr { mem, .. }, Value::ValPtr { mem: other, .. }) => Rc::ptr_eq(mem, other),
+            _ => false,
+        }
+    }
+
+    /// Whether two pointers point to the same cell of the same allocation.
+    pub fn same_cell(&self, other: &Value) -> bool {
+        match (self, other) {
+            (
+                Value::ValPtr { mem, ptr, .. },
+                Value::ValPtr {
+                    mem: other,
+                    ptr: ptr2,
+                    ..
+                },
+            ) => Rc::ptr_eq(mem, other) && *ptr / 4 == *ptr2 / 4,
+            _ => false,
+        }
+    }
 }
 
 impl ops::Add<Value> for Value {